@@ -0,0 +1,587 @@
+use std::mem;
+
+pub const AUDIO:    (u16, u16) = (0xff10, 0xff3f);
+const WAVE_RAM:     (u16, u16) = (0xff30, 0xff3f);
+
+pub const NR10:     u16 = 0xff10;
+pub const NR14:     u16 = 0xff14;
+pub const NR21:     u16 = 0xff16;
+pub const NR24:     u16 = 0xff19;
+pub const NR30:     u16 = 0xff1a;
+pub const NR34:     u16 = 0xff1e;
+pub const NR41:     u16 = 0xff20;
+pub const NR44:     u16 = 0xff23;
+pub const NR50:     u16 = 0xff24;
+pub const NR51:     u16 = 0xff25;
+pub const NR52:     u16 = 0xff26;
+
+const CLOCK:            u32 = 4_194_304;
+// the frame sequencer runs at 512 Hz
+const SEQUENCER_PERIOD: u32 = 8192;
+
+// Bits of NR10-NR52 that read back as set, whatever was written
+const READ_MASKS: [u8; 0x17] = [
+    0x80, 0x3f, 0x00, 0xff, 0xbf,
+    0xff, 0x3f, 0x00, 0xff, 0xbf,
+    0x7f, 0xff, 0x9f, 0xff, 0xbf,
+    0xff, 0xff, 0x00, 0x00, 0xbf,
+    0x00, 0x00, 0x70,
+];
+
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+struct Length {
+    max: u16,
+    counter: u16,
+    enabled: bool,
+}
+
+impl Length {
+    fn new(max: u16) -> Length {
+        Length { max, counter: 0, enabled: false }
+    }
+
+    fn load(&mut self, value: u16) {
+        self.counter = self.max - value;
+    }
+
+    fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    // Returns true when the counter runs out, which silences the channel.
+    fn step(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+}
+
+struct Envelope {
+    initial: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope { initial: 0, increase: false, period: 0, volume: 0, timer: 0 }
+    }
+
+    fn write(&mut self, byte: u8) {
+        self.initial = byte >> 4;
+        self.increase = byte & 0x08 != 0;
+        self.period = byte & 0x07;
+    }
+
+    // The upper five bits of NRx2 double as the DAC power switch
+    fn dac(&self) -> bool {
+        self.initial != 0 || self.increase
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.period;
+    }
+
+    fn step(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+// Channels 1 and 2. Only channel 1 has a sweep unit, channel 2 simply never
+// has its sweep register written.
+struct Square {
+    enabled: bool,
+    duty: u8,
+    position: u8,
+    frequency: u16,
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_timer: u8,
+    sweep_enabled: bool,
+    shadow: u16,
+}
+
+impl Square {
+    fn new() -> Square {
+        Square {
+            enabled: false,
+            duty: 0,
+            position: 0,
+            frequency: 0,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_timer: 0,
+            sweep_enabled: false,
+            shadow: 0,
+        }
+    }
+
+    fn write(&mut self, register: u16, byte: u8) {
+        match register {
+            0 => {
+                self.sweep_period = (byte >> 4) & 0x07;
+                self.sweep_negate = byte & 0x08 != 0;
+                self.sweep_shift = byte & 0x07;
+            },
+            1 => {
+                self.duty = byte >> 6;
+                self.length.load((byte & 0x3f) as u16);
+            },
+            2 => {
+                self.envelope.write(byte);
+                if !self.envelope.dac() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.frequency = (self.frequency & 0x700) | byte as u16,
+            _ => {
+                self.frequency = (self.frequency & 0xff) | ((byte & 0x07) as u16) << 8;
+                self.length.enabled = byte & 0x40 != 0;
+                if byte & 0x80 != 0 {
+                    self.trigger();
+                }
+            },
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac();
+        self.length.trigger();
+        self.timer = (2048 - self.frequency as u32) * 4;
+        self.envelope.trigger();
+        self.shadow = self.frequency;
+        self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+        self.sweep_enabled = self.sweep_period != 0 || self.sweep_shift != 0;
+        if self.sweep_shift != 0 {
+            self.sweep_frequency();
+        }
+    }
+
+    // The next sweep frequency, overflowing past 2047 disables the channel.
+    fn sweep_frequency(&mut self) -> u16 {
+        let delta = self.shadow >> self.sweep_shift;
+        let frequency = if self.sweep_negate { self.shadow - delta } else { self.shadow + delta };
+        if frequency > 2047 {
+            self.enabled = false;
+        }
+        frequency
+    }
+
+    fn step_sweep(&mut self) {
+        self.sweep_timer = self.sweep_timer.saturating_sub(1);
+        if self.sweep_timer != 0 {
+            return;
+        }
+        self.sweep_timer = if self.sweep_period == 0 { 8 } else { self.sweep_period };
+        if self.sweep_enabled && self.sweep_period != 0 {
+            let frequency = self.sweep_frequency();
+            if frequency <= 2047 && self.sweep_shift != 0 {
+                self.shadow = frequency;
+                self.frequency = frequency;
+                // the new frequency is checked for overflow right away
+                self.sweep_frequency();
+            }
+        }
+    }
+
+    fn step_length(&mut self) {
+        if self.length.step() {
+            self.enabled = false;
+        }
+    }
+
+    fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = (2048 - self.frequency as u32) * 4;
+            self.position = (self.position + 1) & 0x07;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && DUTY_CYCLES[self.duty as usize][self.position as usize] != 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+struct Wave {
+    enabled: bool,
+    dac: bool,
+    volume: u8,
+    frequency: u16,
+    timer: u32,
+    position: u8,
+    length: Length,
+    // 32 four bit samples, high nibble first
+    ram: [u8; 16],
+}
+
+impl Wave {
+    fn new() -> Wave {
+        Wave {
+            enabled: false,
+            dac: false,
+            volume: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            length: Length::new(256),
+            ram: [0; 16],
+        }
+    }
+
+    fn write(&mut self, register: u16, byte: u8) {
+        match register {
+            0 => {
+                self.dac = byte & 0x80 != 0;
+                if !self.dac {
+                    self.enabled = false;
+                }
+            },
+            1 => self.length.load(byte as u16),
+            2 => self.volume = (byte >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x700) | byte as u16,
+            _ => {
+                self.frequency = (self.frequency & 0xff) | ((byte & 0x07) as u16) << 8;
+                self.length.enabled = byte & 0x40 != 0;
+                if byte & 0x80 != 0 {
+                    self.enabled = self.dac;
+                    self.length.trigger();
+                    self.timer = (2048 - self.frequency as u32) * 2;
+                    self.position = 0;
+                }
+            },
+        }
+    }
+
+    fn step_length(&mut self) {
+        if self.length.step() {
+            self.enabled = false;
+        }
+    }
+
+    fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = (2048 - self.frequency as u32) * 2;
+            self.position = (self.position + 1) & 0x1f;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let byte = self.ram[self.position as usize / 2];
+        let sample = if self.position & 0x01 == 0 { byte >> 4 } else { byte & 0x0f };
+        // volume codes 0-3 are mute, 100%, 50% and 25%
+        sample >> [4, 0, 1, 2][self.volume as usize]
+    }
+}
+
+struct Noise {
+    enabled: bool,
+    length: Length,
+    envelope: Envelope,
+    shift: u8,
+    narrow: bool,
+    divisor: u8,
+    timer: u32,
+    lfsr: u16,
+}
+
+impl Noise {
+    fn new() -> Noise {
+        Noise {
+            enabled: false,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            shift: 0,
+            narrow: false,
+            divisor: 0,
+            timer: 0,
+            lfsr: 0x7fff,
+        }
+    }
+
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.divisor as usize] << self.shift
+    }
+
+    fn write(&mut self, register: u16, byte: u8) {
+        match register {
+            1 => self.length.load((byte & 0x3f) as u16),
+            2 => {
+                self.envelope.write(byte);
+                if !self.envelope.dac() {
+                    self.enabled = false;
+                }
+            },
+            3 => {
+                self.shift = byte >> 4;
+                self.narrow = byte & 0x08 != 0;
+                self.divisor = byte & 0x07;
+            },
+            _ => {
+                self.length.enabled = byte & 0x40 != 0;
+                if byte & 0x80 != 0 {
+                    self.enabled = self.envelope.dac();
+                    self.length.trigger();
+                    self.timer = self.period();
+                    self.envelope.trigger();
+                    self.lfsr = 0x7fff;
+                }
+            },
+        }
+    }
+
+    fn step_length(&mut self) {
+        if self.length.step() {
+            self.enabled = false;
+        }
+    }
+
+    fn tick(&mut self) {
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period();
+            // 15 bit LFSR, in 7 bit mode the feedback also lands in bit 6
+            let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+            self.lfsr = (self.lfsr >> 1) | feedback << 14;
+            if self.narrow {
+                self.lfsr = (self.lfsr & !0x40) | feedback << 6;
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 0x01 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+pub struct Apu {
+    power: bool,
+    // NR10-NR52 as last written, for reading back
+    registers: [u8; 0x17],
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    sequencer_timer: u32,
+    sequencer_step: u8,
+    // no samples are produced until the host asks for a sample rate
+    sample_rate: Option<u32>,
+    sample_timer: u32,
+    samples: Vec<i16>,
+    // high-pass filter removing the DC offset of the DACs, left and right
+    capacitors: [f32; 2],
+    charge_factor: f32,
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            power: false,
+            registers: [0; 0x17],
+            square1: Square::new(),
+            square2: Square::new(),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            sequencer_timer: SEQUENCER_PERIOD,
+            sequencer_step: 0,
+            sample_rate: None,
+            sample_timer: 0,
+            samples: Vec::new(),
+            capacitors: [0.0; 2],
+            charge_factor: 1.0,
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = Some(sample_rate);
+        self.sample_timer = 0;
+        self.charge_factor = 0.999958f32.powf(CLOCK as f32 / sample_rate as f32);
+    }
+
+    // Interleaved left/right samples produced since the last call
+    pub fn drain_samples(&mut self) -> Vec<i16> {
+        mem::take(&mut self.samples)
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        if address >= WAVE_RAM.0 {
+            return self.wave.ram[(address - WAVE_RAM.0) as usize];
+        }
+        if address == NR52 {
+            let status = (self.square1.enabled as u8)
+                | (self.square2.enabled as u8) << 1
+                | (self.wave.enabled as u8) << 2
+                | (self.noise.enabled as u8) << 3;
+            return 0x70 | (self.power as u8) << 7 | status;
+        }
+        match address {
+            NR10..=NR51 => {
+                let index = (address - NR10) as usize;
+                self.registers[index] | READ_MASKS[index]
+            },
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, byte: u8) {
+        if address >= WAVE_RAM.0 {
+            self.wave.ram[(address - WAVE_RAM.0) as usize] = byte;
+            return;
+        }
+        if address == NR52 {
+            let power = byte & 0x80 != 0;
+            if self.power && !power {
+                // powering off clears every register, wave RAM survives
+                let ram = self.wave.ram;
+                self.registers = [0; 0x17];
+                self.square1 = Square::new();
+                self.square2 = Square::new();
+                self.wave = Wave::new();
+                self.wave.ram = ram;
+                self.noise = Noise::new();
+            } else if !self.power && power {
+                self.sequencer_step = 0;
+            }
+            self.power = power;
+            return;
+        }
+        // while powered off the registers are read-only
+        if !self.power || address > NR52 {
+            return;
+        }
+        self.registers[(address - NR10) as usize] = byte;
+        match address {
+            NR10..=NR14 => self.square1.write(address - NR10, byte),
+            NR21..=NR24 => self.square2.write(address - NR21 + 1, byte),
+            NR30..=NR34 => self.wave.write(address - NR30, byte),
+            NR41..=NR44 => self.noise.write(address - NR41 + 1, byte),
+            _ => {},
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            if self.power {
+                self.square1.tick();
+                self.square2.tick();
+                self.wave.tick();
+                self.noise.tick();
+
+                self.sequencer_timer -= 1;
+                if self.sequencer_timer == 0 {
+                    self.sequencer_timer = SEQUENCER_PERIOD;
+                    self.step_sequencer();
+                }
+            }
+
+            if let Some(sample_rate) = self.sample_rate {
+                self.sample_timer += sample_rate;
+                if self.sample_timer >= CLOCK {
+                    self.sample_timer -= CLOCK;
+                    self.sample();
+                }
+            }
+        }
+    }
+
+    // Length counters at 256 Hz, sweep at 128 Hz and envelopes at 64 Hz
+    fn step_sequencer(&mut self) {
+        if self.sequencer_step & 0x01 == 0 {
+            self.square1.step_length();
+            self.square2.step_length();
+            self.wave.step_length();
+            self.noise.step_length();
+        }
+        if self.sequencer_step == 2 || self.sequencer_step == 6 {
+            self.square1.step_sweep();
+        }
+        if self.sequencer_step == 7 {
+            self.square1.envelope.step();
+            self.square2.envelope.step();
+            self.noise.envelope.step();
+        }
+        self.sequencer_step = (self.sequencer_step + 1) & 0x07;
+    }
+
+    fn sample(&mut self) {
+        let channels = [
+            (self.square1.envelope.dac(), self.square1.output()),
+            (self.square2.envelope.dac(), self.square2.output()),
+            (self.wave.dac, self.wave.output()),
+            (self.noise.envelope.dac(), self.noise.output()),
+        ];
+        let panning = self.registers[(NR51 - NR10) as usize];
+        let volume = self.registers[(NR50 - NR10) as usize];
+
+        let mut mix = [0.0f32; 2];
+        for (i, &(dac, output)) in channels.iter().enumerate() {
+            if !self.power || !dac {
+                continue;
+            }
+            // the DACs map 0-15 onto -1.0 to 1.0
+            let analog = output as f32 / 7.5 - 1.0;
+            if panning & (0x10 << i) != 0 {
+                mix[0] += analog;
+            }
+            if panning & (0x01 << i) != 0 {
+                mix[1] += analog;
+            }
+        }
+        mix[0] *= (((volume >> 4) & 0x07) + 1) as f32;
+        mix[1] *= ((volume & 0x07) + 1) as f32;
+
+        for (side, &value) in mix.iter().enumerate() {
+            let filtered = value - self.capacitors[side];
+            self.capacitors[side] = value - filtered * self.charge_factor;
+            // four channels at up to 8x master volume
+            let sample = filtered / 32.0 * i16::MAX as f32;
+            self.samples.push(sample.max(i16::MIN as f32).min(i16::MAX as f32) as i16);
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::fmt;
+use memory::ROM_HEADER;
+
+const TITLE:                (usize, usize) = (0x0134, 0x0143);
+const MANUFACTURER_CODE:    (usize, usize) = (0x013f, 0x0142);
+const CGB_FLAG:             usize = 0x0143;
+const NEW_LICENSEE_CODE:    (usize, usize) = (0x0144, 0x0145);
+const SGB_FLAG:             usize = 0x0146;
+const CARTRIDGE_TYPE:       usize = 0x0147;
+const ROM_SIZE:             usize = 0x0148;
+const RAM_SIZE:             usize = 0x0149;
+const DESTINATION:          usize = 0x014a;
+const OLD_LICENSEE_CODE:    usize = 0x014b;
+const VERSION:              usize = 0x014c;
+const HEADER_CHECKSUM:      usize = 0x014d;
+const GLOBAL_CHECKSUM:      (usize, usize) = (0x014e, 0x014f);
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Truncated(usize),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    RomSizeMismatch { declared: usize, actual: usize },
+    HeaderChecksum { expected: u8, actual: u8 },
+    GlobalChecksum { expected: u16, actual: u16 },
+    UnsupportedMapper(u8),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CartridgeError::Truncated(length) =>
+                write!(f, "ROM is {} bytes long, too short to contain a cartridge header", length),
+            CartridgeError::UnknownRomSize(code) =>
+                write!(f, "unknown ROM size code ${:02x}", code),
+            CartridgeError::UnknownRamSize(code) =>
+                write!(f, "unknown RAM size code ${:02x}", code),
+            CartridgeError::RomSizeMismatch { declared, actual } =>
+                write!(f, "header declares {} bytes of ROM but the file is {} bytes long", declared, actual),
+            CartridgeError::HeaderChecksum { expected, actual } =>
+                write!(f, "header checksum is ${:02x}, expected ${:02x}", actual, expected),
+            CartridgeError::GlobalChecksum { expected, actual } =>
+                write!(f, "global checksum is ${:04x}, expected ${:04x}", actual, expected),
+            CartridgeError::UnsupportedMapper(cartridge_type) =>
+                write!(f, "cartridge type ${:02x} is not supported", cartridge_type),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mapper {
+    None,
+    MBC1,
+    MBC2,
+    MBC3,
+    MBC5,
+    Unsupported,
+}
+
+pub struct Cartridge {
+    pub rom: Vec<u8>,
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb_flag: u8,
+    pub sgb_flag: u8,
+    pub licensee_code: String,
+    pub cartridge_type: u8,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub destination: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl Cartridge {
+    pub fn new(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        if rom.len() <= ROM_HEADER.1 as usize {
+            return Err(CartridgeError::Truncated(rom.len()));
+        }
+
+        let rom_size = match rom[ROM_SIZE] {
+            code @ 0x00..=0x08 => 0x8000 << code,
+            code => return Err(CartridgeError::UnknownRomSize(code)),
+        };
+        if rom_size != rom.len() {
+            return Err(CartridgeError::RomSizeMismatch { declared: rom_size, actual: rom.len() });
+        }
+
+        let ram_size = match rom[RAM_SIZE] {
+            0x00 => 0,
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            code => return Err(CartridgeError::UnknownRamSize(code)),
+        };
+
+        // CGB cartridges repurposed the tail of the title for the
+        // manufacturer code and the CGB flag.
+        let cgb_flag = rom[CGB_FLAG];
+        let (title, manufacturer_code) = if cgb_flag & 0x80 != 0 {
+            (text(&rom[TITLE.0..MANUFACTURER_CODE.0]), Some(text(&rom[MANUFACTURER_CODE.0..=MANUFACTURER_CODE.1])))
+        } else {
+            (text(&rom[TITLE.0..=TITLE.1]), None)
+        };
+
+        // 0x33 in the old licensee field defers to the new, two character code
+        let licensee_code = if rom[OLD_LICENSEE_CODE] == 0x33 {
+            text(&rom[NEW_LICENSEE_CODE.0..=NEW_LICENSEE_CODE.1])
+        } else {
+            format!("{:02X}", rom[OLD_LICENSEE_CODE])
+        };
+
+        Ok(Cartridge {
+            title,
+            manufacturer_code,
+            cgb_flag,
+            sgb_flag: rom[SGB_FLAG],
+            licensee_code,
+            cartridge_type: rom[CARTRIDGE_TYPE],
+            rom_size,
+            ram_size,
+            destination: rom[DESTINATION],
+            version: rom[VERSION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: (rom[GLOBAL_CHECKSUM.0] as u16) << 8 | rom[GLOBAL_CHECKSUM.1] as u16,
+            rom,
+        })
+    }
+
+    pub fn mapper(&self) -> Mapper {
+        match self.cartridge_type {
+            0x00 | 0x08 | 0x09 => Mapper::None,
+            0x01..=0x03 => Mapper::MBC1,
+            0x05 | 0x06 => Mapper::MBC2,
+            0x0f..=0x13 => Mapper::MBC3,
+            0x19..=0x1e => Mapper::MBC5,
+            _ => Mapper::Unsupported,
+        }
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type, 0x03 | 0x06 | 0x09 | 0x0d | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e | 0x22 | 0xff)
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0b => "MMM01",
+            0x0c => "MMM01+RAM",
+            0x0d => "MMM01+RAM+BATTERY",
+            0x0f => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1a => "MBC5+RAM",
+            0x1b => "MBC5+RAM+BATTERY",
+            0x1c => "MBC5+RUMBLE",
+            0x1d => "MBC5+RUMBLE+RAM",
+            0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xfc => "POCKET CAMERA",
+            0xfd => "BANDAI TAMA5",
+            0xfe => "HuC3",
+            0xff => "HuC1+RAM+BATTERY",
+            _ => "UNKNOWN",
+        }
+    }
+
+    // The boot ROM refuses to start a cartridge whose header checksum is off.
+    pub fn verify_header_checksum(&self) -> Result<(), CartridgeError> {
+        let expected = self.rom[TITLE.0..=VERSION].iter()
+            .fold(0u8, |x, &byte| x.wrapping_sub(byte).wrapping_sub(1));
+        if expected != self.header_checksum {
+            return Err(CartridgeError::HeaderChecksum { expected, actual: self.header_checksum });
+        }
+        Ok(())
+    }
+
+    // The global checksum is never checked by the hardware, so plenty of
+    // homebrew gets it wrong.
+    pub fn verify_global_checksum(&self) -> Result<(), CartridgeError> {
+        let expected = self.rom.iter().enumerate()
+            .filter(|&(i, _)| i != GLOBAL_CHECKSUM.0 && i != GLOBAL_CHECKSUM.1)
+            .fold(0u16, |x, (_, &byte)| x.wrapping_add(byte as u16));
+        if expected != self.global_checksum {
+            return Err(CartridgeError::GlobalChecksum { expected, actual: self.global_checksum });
+        }
+        Ok(())
+    }
+
+    pub fn dump(&self) {
+        println!("Title:             {}", self.title);
+        if let Some(ref code) = self.manufacturer_code {
+            println!("Manufacturer code: {}", code);
+        }
+        println!("CGB flag:          ${:02x}", self.cgb_flag);
+        println!("SGB flag:          ${:02x}", self.sgb_flag);
+        println!("Licensee code:     {}", self.licensee_code);
+        println!("Cartridge type:    ${:02x} ({})", self.cartridge_type, self.type_name());
+        println!("ROM size:          {} KiB", self.rom_size / 1024);
+        println!("RAM size:          {} KiB", self.ram_size / 1024);
+        println!("Destination:       {}", if self.destination == 0 { "Japanese" } else { "Non-Japanese" });
+        println!("Version:           {}", self.version);
+        match self.verify_header_checksum() {
+            Ok(_) => println!("Header checksum:   ${:02x} (ok)", self.header_checksum),
+            Err(e) => println!("Header checksum:   {}", e),
+        }
+        match self.verify_global_checksum() {
+            Ok(_) => println!("Global checksum:   ${:04x} (ok)", self.global_checksum),
+            Err(e) => println!("Global checksum:   {}", e),
+        }
+    }
+}
+
+// Header strings are padded with zeros
+fn text(bytes: &[u8]) -> String {
+    bytes.iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 32 KiB ROM-only image with a valid header checksum
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0u8; 0x8000];
+        rom[TITLE.0..TITLE.0 + 4].copy_from_slice(b"TEST");
+        rom[HEADER_CHECKSUM] = rom[TITLE.0..=VERSION].iter()
+            .fold(0u8, |x, &byte| x.wrapping_sub(byte).wrapping_sub(1));
+        rom
+    }
+
+    #[test]
+    fn parses_a_minimal_header() {
+        let cartridge = Cartridge::new(rom()).unwrap();
+        assert_eq!(cartridge.title, "TEST");
+        assert_eq!(cartridge.rom_size, 0x8000);
+        assert_eq!(cartridge.ram_size, 0);
+        assert!(cartridge.mapper() == Mapper::None);
+        assert!(cartridge.verify_header_checksum().is_ok());
+    }
+
+    #[test]
+    fn rejects_truncated_roms() {
+        let rom = rom()[..ROM_HEADER.1 as usize].to_vec();
+        assert!(matches!(Cartridge::new(rom), Err(CartridgeError::Truncated(0x014f))));
+        assert!(matches!(Cartridge::new(Vec::new()), Err(CartridgeError::Truncated(0))));
+    }
+
+    #[test]
+    fn rejects_unknown_size_codes() {
+        let mut rom = rom();
+        rom[ROM_SIZE] = 0x09;
+        assert!(matches!(Cartridge::new(rom), Err(CartridgeError::UnknownRomSize(0x09))));
+
+        let mut rom = self::rom();
+        rom[RAM_SIZE] = 0x06;
+        assert!(matches!(Cartridge::new(rom), Err(CartridgeError::UnknownRamSize(0x06))));
+    }
+
+    #[test]
+    fn rejects_a_rom_size_mismatch() {
+        let mut rom = rom();
+        rom[ROM_SIZE] = 0x01;
+        assert!(matches!(
+            Cartridge::new(rom),
+            Err(CartridgeError::RomSizeMismatch { declared: 0x10000, actual: 0x8000 })
+        ));
+    }
+
+    #[test]
+    fn checks_the_header_checksum() {
+        let mut rom = rom();
+        let expected = rom[HEADER_CHECKSUM];
+        rom[HEADER_CHECKSUM] = expected.wrapping_add(1);
+        let cartridge = Cartridge::new(rom).unwrap();
+        match cartridge.verify_header_checksum() {
+            Err(CartridgeError::HeaderChecksum { expected: e, actual }) => {
+                assert_eq!(e, expected);
+                assert_eq!(actual, expected.wrapping_add(1));
+            },
+            _ => panic!("bad header checksum went unnoticed"),
+        }
+    }
+
+    #[test]
+    fn checks_the_global_checksum() {
+        let mut rom = rom();
+        let sum = rom.iter().fold(0u16, |x, &byte| x.wrapping_add(byte as u16));
+        rom[GLOBAL_CHECKSUM.0] = (sum >> 8) as u8;
+        rom[GLOBAL_CHECKSUM.1] = sum as u8;
+        assert!(Cartridge::new(rom.clone()).unwrap().verify_global_checksum().is_ok());
+
+        rom[0x0200] = 0xff;
+        assert!(matches!(
+            Cartridge::new(rom).unwrap().verify_global_checksum(),
+            Err(CartridgeError::GlobalChecksum { expected, actual }) if expected == sum.wrapping_add(0xff) && actual == sum
+        ));
+    }
+}
@@ -1,99 +1,517 @@
-use std::fs::File;
-use std::io::Read;
-use instructions::{Instructions, get_instruction, get_debug, get_prefixed_instruction, get_prefixed_debug};
+use std::fmt;
+use std::path::PathBuf;
+use mbc::MemoryBankController;
+use instructions::{Instructions, Prefixed, Target, INSTRUCTIONS, PREFIXED, disassemble};
 use registers::{Registers, Register};
-use memory::{Memory, IO};
-use flags::Flags;
-use stack::Stack;
+use memory::{Memory, BootRomError, IO};
+use flags::{Flags, Condition};
+use interrupts::INTERRUPTS;
+use joypad::Button;
+use serial::SerialEndpoint;
+use watchpoint::{Watchpoint, WatchHit};
 
+// The opcode that locked the CPU up. Like the hardware, the CPU stops for
+// good with PC on it.
+#[derive(Clone, Copy)]
+pub struct IllegalOpcode {
+    pub opcode: u8,
+    pub address: u16,
+}
+
+impl fmt::Display for IllegalOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "illegal opcode ${:02x} at ${:04x}", self.opcode, self.address)
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
-    rom: Vec<u8>,
     registers: Registers,
     memory: Memory,
-    stack: Stack,
+    ime: bool,
+    // EI only takes effect after the following instruction
+    ime_scheduled: bool,
+    halted: bool,
+    halt_bug: bool,
+    locked_up: Option<IllegalOpcode>,
+    branch_taken: bool,
     verbose: bool
 }
 
 impl CPU {
-    pub fn new(rom: String, verbose: bool) -> CPU {
-        let registers = Registers::new();
-        let memory = Memory::new();
-        let stack = Stack::new();
+    pub fn new(mbc: Box<dyn MemoryBankController>, boot_rom: Option<Vec<u8>>, verbose: bool) -> Result<CPU, BootRomError> {
+        let skip_boot = boot_rom.is_none();
 
-        let mut file = File::open(rom).unwrap();
-        let mut rom = Vec::<u8>::new();
-        let _ = file.read_to_end(&mut rom);
+        let registers = Registers::new();
+        let memory = Memory::new(mbc, boot_rom)?;
 
-        CPU {
-            rom,
+        let mut cpu = CPU {
             registers,
             verbose,
             memory,
-            stack,
+            ime: false,
+            ime_scheduled: false,
+            halted: false,
+            halt_bug: false,
+            locked_up: None,
+            branch_taken: false,
+        };
+        if skip_boot {
+            cpu.skip_boot();
+        }
+        Ok(cpu)
+    }
+
+    pub fn attach_save(&mut self, path: PathBuf) {
+        self.memory.attach_save(path);
+    }
+
+    pub fn flush_save(&mut self) {
+        self.memory.flush_save();
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    // The illegal opcode the CPU hung on, if it did
+    pub fn locked_up(&self) -> Option<IllegalOpcode> {
+        self.locked_up
+    }
+
+    // Memory access for tools, bypassing the restrictions of the CPU's own bus
+    pub fn peek(&self, address: u16) -> u8 {
+        self.memory.read_internal(address)
+    }
+
+    pub fn poke(&mut self, address: u16, byte: u8) {
+        self.memory.write_internal(address, byte);
+    }
+
+    // The instruction at `address` and its length
+    pub fn disassemble(&self, address: u16) -> (String, u16) {
+        disassemble(address, |address| self.memory.read_internal(address))
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        self.memory.watchpoints()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.memory.add_watchpoint(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        self.memory.remove_watchpoint(index)
+    }
+
+    // The first watchpoint hit since the last call
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.memory.take_watch_hit()
+    }
+
+    // Plugs something into the link port, replacing what was there.
+    pub fn connect_serial(&mut self, endpoint: Box<dyn SerialEndpoint>) {
+        self.memory.connect_serial(endpoint);
+    }
+
+    // Input from the host, pressing a selected button requests the joypad
+    // interrupt.
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.memory.set_button(button, pressed);
+    }
+
+    // Starts collecting audio at the given rate, the host has to keep
+    // draining the samples from then on.
+    pub fn enable_audio(&mut self, sample_rate: u32) {
+        self.memory.apu_mut().set_sample_rate(sample_rate);
+    }
+
+    // Interleaved stereo samples produced since the last call
+    pub fn drain_audio(&mut self) -> Vec<i16> {
+        self.memory.apu_mut().drain_samples()
+    }
+
+    // Whether the PPU entered VBlank since the last call
+    pub fn frame_ready(&mut self) -> bool {
+        self.memory.ppu_mut().frame_ready()
+    }
+
+    // 160x144 shades, 0 being white and 3 black
+    pub fn framebuffer(&self) -> &[u8] {
+        self.memory.ppu().framebuffer()
+    }
+
+    // Whether the cartridge's rumble motor is running
+    pub fn rumble_active(&self) -> bool {
+        self.memory.rumble_active()
+    }
+
+    // Without a boot ROM, start at the cartridge entry point with the
+    // registers set up the way the DMG boot ROM leaves them.
+    fn skip_boot(&mut self) {
+        self.registers.set_af(0x01b0);
+        self.registers.set_bc(0x0013);
+        self.registers.set_de(0x00d8);
+        self.registers.set_hl(0x014d);
+        self.registers.sp = 0xfffe;
+        self.registers.jump(0x0100);
+        self.memory.skip_boot();
+    }
+
+    // Executes a single instruction, or services an interrupt, and returns the
+    // number of T-cycles it took.
+    pub fn step(&mut self) -> u32 {
+        let cycles = self.advance();
+        self.memory.tick(cycles);
+        cycles
+    }
+
+    // Services interrupts and HALT, or runs the next instruction.
+    fn advance(&mut self) -> u32 {
+        // not even interrupts get a locked up CPU going again
+        if self.locked_up.is_some() {
+            return 4;
+        }
+        let pending = self.memory.pending_interrupts();
+        if self.halted {
+            // any pending interrupt wakes the CPU up, even with IME cleared
+            if pending == 0 {
+                return 4;
+            }
+            self.halted = false;
+        }
+        if self.ime && pending != 0 {
+            return self.dispatch(pending);
+        }
+        let enable_ime = self.ime_scheduled;
+
+        let cycles = self.execute_next();
+        if enable_ime && self.ime_scheduled {
+            self.ime = true;
+            self.ime_scheduled = false;
         }
+        cycles
     }
 
-    pub fn run(&mut self) {
-        let mut prefixed = false;
-        loop {
-            let opcode = self.read_8();
-            let instruction: &Instructions;
-            if !prefixed {
-                instruction = get_instruction(opcode);
-            } else {
-                instruction = get_prefixed_instruction(opcode);
+    // Pushes PC and jumps to the vector of the highest priority pending
+    // interrupt, which takes 5 M-cycles.
+    fn dispatch(&mut self, pending: u8) -> u32 {
+        for &interrupt in INTERRUPTS.iter() {
+            if pending & interrupt.mask() != 0 {
+                self.ime = false;
+                self.memory.acknowledge_interrupt(interrupt);
+                let pc = self.registers.pc;
+                self.push_16(pc);
+                self.registers.jump(interrupt.vector());
+                break;
             }
-            let data = match instruction {
-                Instructions::Prefixed => { prefixed = true; None },
-                Instructions::LD_SP_D16 => self.ld_sp_d16(),
-                Instructions::XOR_A => self.xora(),
-                Instructions::LD_HL_D16 => self.ld_hl_d16(),
-                Instructions::LD_HLD_A => self.ld_hld_a(),
-                Instructions::BIT_7_H => self.bit_h(Flags::Z as u8),
-                Instructions::JR_NZ_8 => self.jr_nz_8(),
-                Instructions::LD_C_D8 => self.ld_x_d8(Register::C),
-                Instructions::LD_A_D8 => self.ld_a_d8(),
-                Instructions::LD_FFC_A => self.ld_ffc_a(),
-                Instructions::INC_C => self.inc_c(),
-                Instructions::LD_HL_A => self.ld_hl_a(),
-                Instructions::LDH_D8_A => self.ldh_d8_a(),
-                Instructions::LD_DE_D16 => self.ld_de_d16(),
-                Instructions::LD_A_DE => self.ld_a_de(),
-                Instructions::CALL_A16 => self.call_a16(),
-                Instructions::LD_C_A => self.ld_c_a(),
-                Instructions::LD_B_D8 => self.ld_x_d8(Register::B),
-                Instructions::PUSH_BC => self.push(Register::BC),
-                Instructions::RL_C => self.rl(Register::C),
-                Instructions::Unknown => self.panic(opcode)
-            };
-            if self.verbose && *instruction != Instructions::Prefixed {
-                if data.is_some() {
-                    if prefixed {
-                        println!("{}", get_prefixed_debug(opcode, data.unwrap()));
-                        prefixed = false;
-                    } else {
-                        println!("{}", get_debug(opcode, data.unwrap()));
-                    }
+        }
+        20
+    }
+
+    fn execute_next(&mut self) -> u32 {
+        if self.verbose {
+            let pc = self.registers.pc;
+            let (text, _) = disassemble(pc, |address| self.memory.read_internal(address));
+            println!("{}", text);
+        }
+        let address = self.registers.pc;
+        let opcode = self.memory.fetch(address);
+        let instruction = &INSTRUCTIONS[opcode as usize];
+        if instruction.instruction == Instructions::Unknown {
+            self.locked_up = Some(IllegalOpcode { opcode, address });
+            return 4;
+        }
+        // the HALT bug makes the CPU fail to advance PC past the next opcode
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.registers.step(1);
+        }
+        match instruction.instruction {
+            Instructions::Prefixed => {
+                let opcode = self.read_8();
+                self.prefixed(opcode);
+                PREFIXED[opcode as usize].cycles as u32
+            },
+            _ => {
+                self.branch_taken = false;
+                self.execute(opcode, &instruction.instruction);
+                if self.branch_taken {
+                    instruction.cycles_taken as u32
                 } else {
-                    if prefixed {
-                        println!("{}", get_prefixed_debug(opcode, vec![]));
-                        prefixed = false;
-                    } else {
-                        println!("{}", get_debug(opcode, vec![]));
-                    }
+                    instruction.cycles as u32
                 }
-                //self.registers.dump();
-            }
+            },
         }
     }
 
-    fn panic(&mut self, opcode: u8) -> Option<Vec<usize>> {
-        self.registers.dump();
-        panic!("0x{:02x} Unknown opcode!", opcode);
+    fn execute(&mut self, opcode: u8, instruction: &Instructions) {
+        match *instruction {
+            Instructions::NOP => self.nop(),
+            Instructions::LD_BC_D16 => self.ld_xx_d16(Register::BC),
+            Instructions::LD_BC_A => self.ld_xx_a(Register::BC),
+            Instructions::INC_BC => self.inc_xx(Register::BC),
+            Instructions::INC_B => self.inc_x(Register::B),
+            Instructions::DEC_B => self.dec_x(Register::B),
+            Instructions::LD_B_D8 => self.ld_x_d8(Register::B),
+            Instructions::RLCA => self.rlca(),
+            Instructions::LD_A16_SP => self.ld_a16_sp(),
+            Instructions::ADD_HL_BC => self.add_hl_xx(Register::BC),
+            Instructions::LD_A_BC => self.ld_a_xx(Register::BC),
+            Instructions::DEC_BC => self.dec_xx(Register::BC),
+            Instructions::INC_C => self.inc_x(Register::C),
+            Instructions::DEC_C => self.dec_x(Register::C),
+            Instructions::LD_C_D8 => self.ld_x_d8(Register::C),
+            Instructions::RRCA => self.rrca(),
+            Instructions::STOP => self.stop(),
+            Instructions::LD_DE_D16 => self.ld_xx_d16(Register::DE),
+            Instructions::LD_DE_A => self.ld_xx_a(Register::DE),
+            Instructions::INC_DE => self.inc_xx(Register::DE),
+            Instructions::INC_D => self.inc_x(Register::D),
+            Instructions::DEC_D => self.dec_x(Register::D),
+            Instructions::LD_D_D8 => self.ld_x_d8(Register::D),
+            Instructions::RLA => self.rla(),
+            Instructions::JR_8 => self.jr_8(),
+            Instructions::ADD_HL_DE => self.add_hl_xx(Register::DE),
+            Instructions::LD_A_DE => self.ld_a_xx(Register::DE),
+            Instructions::DEC_DE => self.dec_xx(Register::DE),
+            Instructions::INC_E => self.inc_x(Register::E),
+            Instructions::DEC_E => self.dec_x(Register::E),
+            Instructions::LD_E_D8 => self.ld_x_d8(Register::E),
+            Instructions::RRA => self.rra(),
+            Instructions::JR_NZ_8 => self.jr_cc_8(Condition::NZ),
+            Instructions::LD_HL_D16 => self.ld_xx_d16(Register::HL),
+            Instructions::LD_HLI_A => self.ld_hli_a(),
+            Instructions::INC_HL => self.inc_xx(Register::HL),
+            Instructions::INC_H => self.inc_x(Register::H),
+            Instructions::DEC_H => self.dec_x(Register::H),
+            Instructions::LD_H_D8 => self.ld_x_d8(Register::H),
+            Instructions::DAA => self.daa(),
+            Instructions::JR_Z_8 => self.jr_cc_8(Condition::Z),
+            Instructions::ADD_HL_HL => self.add_hl_xx(Register::HL),
+            Instructions::LD_A_HLI => self.ld_a_hli(),
+            Instructions::DEC_HL => self.dec_xx(Register::HL),
+            Instructions::INC_L => self.inc_x(Register::L),
+            Instructions::DEC_L => self.dec_x(Register::L),
+            Instructions::LD_L_D8 => self.ld_x_d8(Register::L),
+            Instructions::CPL => self.cpl(),
+            Instructions::JR_NC_8 => self.jr_cc_8(Condition::NC),
+            Instructions::LD_SP_D16 => self.ld_xx_d16(Register::SP),
+            Instructions::LD_HLD_A => self.ld_hld_a(),
+            Instructions::INC_SP => self.inc_xx(Register::SP),
+            Instructions::INC_HL_IND => self.inc_hl_ind(),
+            Instructions::DEC_HL_IND => self.dec_hl_ind(),
+            Instructions::LD_HL_D8 => self.ld_hl_d8(),
+            Instructions::SCF => self.scf(),
+            Instructions::JR_C_8 => self.jr_cc_8(Condition::C),
+            Instructions::ADD_HL_SP => self.add_hl_xx(Register::SP),
+            Instructions::LD_A_HLD => self.ld_a_hld(),
+            Instructions::DEC_SP => self.dec_xx(Register::SP),
+            Instructions::INC_A => self.inc_x(Register::A),
+            Instructions::DEC_A => self.dec_x(Register::A),
+            Instructions::LD_A_D8 => self.ld_x_d8(Register::A),
+            Instructions::CCF => self.ccf(),
+            Instructions::LD_B_B => self.ld_x_y(Register::B, Register::B),
+            Instructions::LD_B_C => self.ld_x_y(Register::B, Register::C),
+            Instructions::LD_B_D => self.ld_x_y(Register::B, Register::D),
+            Instructions::LD_B_E => self.ld_x_y(Register::B, Register::E),
+            Instructions::LD_B_H => self.ld_x_y(Register::B, Register::H),
+            Instructions::LD_B_L => self.ld_x_y(Register::B, Register::L),
+            Instructions::LD_B_HL => self.ld_x_hl(Register::B),
+            Instructions::LD_B_A => self.ld_x_y(Register::B, Register::A),
+            Instructions::LD_C_B => self.ld_x_y(Register::C, Register::B),
+            Instructions::LD_C_C => self.ld_x_y(Register::C, Register::C),
+            Instructions::LD_C_D => self.ld_x_y(Register::C, Register::D),
+            Instructions::LD_C_E => self.ld_x_y(Register::C, Register::E),
+            Instructions::LD_C_H => self.ld_x_y(Register::C, Register::H),
+            Instructions::LD_C_L => self.ld_x_y(Register::C, Register::L),
+            Instructions::LD_C_HL => self.ld_x_hl(Register::C),
+            Instructions::LD_C_A => self.ld_x_y(Register::C, Register::A),
+            Instructions::LD_D_B => self.ld_x_y(Register::D, Register::B),
+            Instructions::LD_D_C => self.ld_x_y(Register::D, Register::C),
+            Instructions::LD_D_D => self.ld_x_y(Register::D, Register::D),
+            Instructions::LD_D_E => self.ld_x_y(Register::D, Register::E),
+            Instructions::LD_D_H => self.ld_x_y(Register::D, Register::H),
+            Instructions::LD_D_L => self.ld_x_y(Register::D, Register::L),
+            Instructions::LD_D_HL => self.ld_x_hl(Register::D),
+            Instructions::LD_D_A => self.ld_x_y(Register::D, Register::A),
+            Instructions::LD_E_B => self.ld_x_y(Register::E, Register::B),
+            Instructions::LD_E_C => self.ld_x_y(Register::E, Register::C),
+            Instructions::LD_E_D => self.ld_x_y(Register::E, Register::D),
+            Instructions::LD_E_E => self.ld_x_y(Register::E, Register::E),
+            Instructions::LD_E_H => self.ld_x_y(Register::E, Register::H),
+            Instructions::LD_E_L => self.ld_x_y(Register::E, Register::L),
+            Instructions::LD_E_HL => self.ld_x_hl(Register::E),
+            Instructions::LD_E_A => self.ld_x_y(Register::E, Register::A),
+            Instructions::LD_H_B => self.ld_x_y(Register::H, Register::B),
+            Instructions::LD_H_C => self.ld_x_y(Register::H, Register::C),
+            Instructions::LD_H_D => self.ld_x_y(Register::H, Register::D),
+            Instructions::LD_H_E => self.ld_x_y(Register::H, Register::E),
+            Instructions::LD_H_H => self.ld_x_y(Register::H, Register::H),
+            Instructions::LD_H_L => self.ld_x_y(Register::H, Register::L),
+            Instructions::LD_H_HL => self.ld_x_hl(Register::H),
+            Instructions::LD_H_A => self.ld_x_y(Register::H, Register::A),
+            Instructions::LD_L_B => self.ld_x_y(Register::L, Register::B),
+            Instructions::LD_L_C => self.ld_x_y(Register::L, Register::C),
+            Instructions::LD_L_D => self.ld_x_y(Register::L, Register::D),
+            Instructions::LD_L_E => self.ld_x_y(Register::L, Register::E),
+            Instructions::LD_L_H => self.ld_x_y(Register::L, Register::H),
+            Instructions::LD_L_L => self.ld_x_y(Register::L, Register::L),
+            Instructions::LD_L_HL => self.ld_x_hl(Register::L),
+            Instructions::LD_L_A => self.ld_x_y(Register::L, Register::A),
+            Instructions::LD_HL_B => self.ld_hl_x(Register::B),
+            Instructions::LD_HL_C => self.ld_hl_x(Register::C),
+            Instructions::LD_HL_D => self.ld_hl_x(Register::D),
+            Instructions::LD_HL_E => self.ld_hl_x(Register::E),
+            Instructions::LD_HL_H => self.ld_hl_x(Register::H),
+            Instructions::LD_HL_L => self.ld_hl_x(Register::L),
+            Instructions::HALT => self.halt(),
+            Instructions::LD_HL_A => self.ld_hl_x(Register::A),
+            Instructions::LD_A_B => self.ld_x_y(Register::A, Register::B),
+            Instructions::LD_A_C => self.ld_x_y(Register::A, Register::C),
+            Instructions::LD_A_D => self.ld_x_y(Register::A, Register::D),
+            Instructions::LD_A_E => self.ld_x_y(Register::A, Register::E),
+            Instructions::LD_A_H => self.ld_x_y(Register::A, Register::H),
+            Instructions::LD_A_L => self.ld_x_y(Register::A, Register::L),
+            Instructions::LD_A_HL => self.ld_x_hl(Register::A),
+            Instructions::LD_A_A => self.ld_x_y(Register::A, Register::A),
+            Instructions::ADD_A_B => self.add_a_x(Register::B, false),
+            Instructions::ADD_A_C => self.add_a_x(Register::C, false),
+            Instructions::ADD_A_D => self.add_a_x(Register::D, false),
+            Instructions::ADD_A_E => self.add_a_x(Register::E, false),
+            Instructions::ADD_A_H => self.add_a_x(Register::H, false),
+            Instructions::ADD_A_L => self.add_a_x(Register::L, false),
+            Instructions::ADD_A_HL => self.add_a_hl(false),
+            Instructions::ADD_A_A => self.add_a_x(Register::A, false),
+            Instructions::ADC_A_B => self.add_a_x(Register::B, true),
+            Instructions::ADC_A_C => self.add_a_x(Register::C, true),
+            Instructions::ADC_A_D => self.add_a_x(Register::D, true),
+            Instructions::ADC_A_E => self.add_a_x(Register::E, true),
+            Instructions::ADC_A_H => self.add_a_x(Register::H, true),
+            Instructions::ADC_A_L => self.add_a_x(Register::L, true),
+            Instructions::ADC_A_HL => self.add_a_hl(true),
+            Instructions::ADC_A_A => self.add_a_x(Register::A, true),
+            Instructions::SUB_B => self.sub_a_x(Register::B, false),
+            Instructions::SUB_C => self.sub_a_x(Register::C, false),
+            Instructions::SUB_D => self.sub_a_x(Register::D, false),
+            Instructions::SUB_E => self.sub_a_x(Register::E, false),
+            Instructions::SUB_H => self.sub_a_x(Register::H, false),
+            Instructions::SUB_L => self.sub_a_x(Register::L, false),
+            Instructions::SUB_HL => self.sub_a_hl(false),
+            Instructions::SUB_A => self.sub_a_x(Register::A, false),
+            Instructions::SBC_A_B => self.sub_a_x(Register::B, true),
+            Instructions::SBC_A_C => self.sub_a_x(Register::C, true),
+            Instructions::SBC_A_D => self.sub_a_x(Register::D, true),
+            Instructions::SBC_A_E => self.sub_a_x(Register::E, true),
+            Instructions::SBC_A_H => self.sub_a_x(Register::H, true),
+            Instructions::SBC_A_L => self.sub_a_x(Register::L, true),
+            Instructions::SBC_A_HL => self.sub_a_hl(true),
+            Instructions::SBC_A_A => self.sub_a_x(Register::A, true),
+            Instructions::AND_B => self.and_x(Register::B),
+            Instructions::AND_C => self.and_x(Register::C),
+            Instructions::AND_D => self.and_x(Register::D),
+            Instructions::AND_E => self.and_x(Register::E),
+            Instructions::AND_H => self.and_x(Register::H),
+            Instructions::AND_L => self.and_x(Register::L),
+            Instructions::AND_HL => self.and_hl(),
+            Instructions::AND_A => self.and_x(Register::A),
+            Instructions::XOR_B => self.xor_x(Register::B),
+            Instructions::XOR_C => self.xor_x(Register::C),
+            Instructions::XOR_D => self.xor_x(Register::D),
+            Instructions::XOR_E => self.xor_x(Register::E),
+            Instructions::XOR_H => self.xor_x(Register::H),
+            Instructions::XOR_L => self.xor_x(Register::L),
+            Instructions::XOR_HL => self.xor_hl(),
+            Instructions::XOR_A => self.xor_x(Register::A),
+            Instructions::OR_B => self.or_x(Register::B),
+            Instructions::OR_C => self.or_x(Register::C),
+            Instructions::OR_D => self.or_x(Register::D),
+            Instructions::OR_E => self.or_x(Register::E),
+            Instructions::OR_H => self.or_x(Register::H),
+            Instructions::OR_L => self.or_x(Register::L),
+            Instructions::OR_HL => self.or_hl(),
+            Instructions::OR_A => self.or_x(Register::A),
+            Instructions::CP_B => self.cp_x(Register::B),
+            Instructions::CP_C => self.cp_x(Register::C),
+            Instructions::CP_D => self.cp_x(Register::D),
+            Instructions::CP_E => self.cp_x(Register::E),
+            Instructions::CP_H => self.cp_x(Register::H),
+            Instructions::CP_L => self.cp_x(Register::L),
+            Instructions::CP_HL => self.cp_hl(),
+            Instructions::CP_A => self.cp_x(Register::A),
+            Instructions::RET_NZ => self.ret_cc(Condition::NZ),
+            Instructions::POP_BC => self.pop(Register::BC),
+            Instructions::JP_NZ_A16 => self.jp_cc_a16(Condition::NZ),
+            Instructions::JP_A16 => self.jp_a16(),
+            Instructions::CALL_NZ_A16 => self.call_cc_a16(Condition::NZ),
+            Instructions::PUSH_BC => self.push(Register::BC),
+            Instructions::ADD_A_D8 => self.add_a_d8(false),
+            Instructions::RST_00H => self.rst(0x00),
+            Instructions::RET_Z => self.ret_cc(Condition::Z),
+            Instructions::RET => self.ret(),
+            Instructions::JP_Z_A16 => self.jp_cc_a16(Condition::Z),
+            Instructions::CALL_Z_A16 => self.call_cc_a16(Condition::Z),
+            Instructions::CALL_A16 => self.call_a16(),
+            Instructions::ADC_A_D8 => self.add_a_d8(true),
+            Instructions::RST_08H => self.rst(0x08),
+            Instructions::RET_NC => self.ret_cc(Condition::NC),
+            Instructions::POP_DE => self.pop(Register::DE),
+            Instructions::JP_NC_A16 => self.jp_cc_a16(Condition::NC),
+            Instructions::CALL_NC_A16 => self.call_cc_a16(Condition::NC),
+            Instructions::PUSH_DE => self.push(Register::DE),
+            Instructions::SUB_D8 => self.sub_a_d8(false),
+            Instructions::RST_10H => self.rst(0x10),
+            Instructions::RET_C => self.ret_cc(Condition::C),
+            Instructions::RETI => self.reti(),
+            Instructions::JP_C_A16 => self.jp_cc_a16(Condition::C),
+            Instructions::CALL_C_A16 => self.call_cc_a16(Condition::C),
+            Instructions::SBC_A_D8 => self.sub_a_d8(true),
+            Instructions::RST_18H => self.rst(0x18),
+            Instructions::LDH_D8_A => self.ldh_d8_a(),
+            Instructions::POP_HL => self.pop(Register::HL),
+            Instructions::LD_FFC_A => self.ld_ffc_a(),
+            Instructions::PUSH_HL => self.push(Register::HL),
+            Instructions::AND_D8 => self.and_d8(),
+            Instructions::RST_20H => self.rst(0x20),
+            Instructions::ADD_SP_R8 => self.add_sp_r8(),
+            Instructions::JP_HL => self.jp_hl(),
+            Instructions::LD_A16_A => self.ld_a16_a(),
+            Instructions::XOR_D8 => self.xor_d8(),
+            Instructions::RST_28H => self.rst(0x28),
+            Instructions::LDH_A_D8 => self.ldh_a_d8(),
+            Instructions::POP_AF => self.pop(Register::AF),
+            Instructions::LD_A_FFC => self.ld_a_ffc(),
+            Instructions::DI => self.di(),
+            Instructions::PUSH_AF => self.push(Register::AF),
+            Instructions::OR_D8 => self.or_d8(),
+            Instructions::RST_30H => self.rst(0x30),
+            Instructions::LD_HL_SP_R8 => self.ld_hl_sp_r8(),
+            Instructions::LD_SP_HL => self.ld_sp_hl(),
+            Instructions::LD_A_A16 => self.ld_a_a16(),
+            Instructions::EI => self.ei(),
+            Instructions::CP_D8 => self.cp_d8(),
+            Instructions::RST_38H => self.rst(0x38),
+            // both are dealt with by execute_next before getting here
+            Instructions::Prefixed | Instructions::Unknown => unreachable!("0x{:02x} can't be executed", opcode)
+        }
     }
 
     pub fn read_8(&mut self) -> u8 {
-        let byte = self.rom[self.registers.pc as usize];
+        let byte = self.memory.read(self.registers.pc);
         self.registers.step(1);
         byte
     }
@@ -104,164 +522,969 @@ impl CPU {
         (y as u16) << 8 | x as u16
     }
 
-    fn ld_sp_d16(&mut self) -> Option<Vec<usize>> {
-        let data = self.read_16();
-        self.registers.sp = data;
-        Some(vec![data as usize])
+    // Evaluates a branch condition and remembers the outcome, taken branches
+    // cost more cycles.
+    fn condition(&mut self, condition: Condition) -> bool {
+        let taken = match condition {
+            Condition::NZ => self.registers.get_flag_z() == 0,
+            Condition::Z => self.registers.get_flag_z() == 1,
+            Condition::NC => self.registers.get_flag_c() == 0,
+            Condition::C => self.registers.get_flag_c() == 1,
+        };
+        self.branch_taken = taken;
+        taken
+    }
+
+    fn nop(&mut self) {}
+
+    fn stop(&mut self) {
+        // STOP is followed by a padding byte
+        self.read_8();
+        self.halted = true;
+    }
+
+    fn halt(&mut self) {
+        // With IME cleared and an interrupt already pending, HALT exits
+        // immediately and the next opcode is read twice.
+        if !self.ime && self.memory.pending_interrupts() != 0 {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
     }
 
-    fn rl(&mut self, register: Register) -> Option<Vec<usize>> {
-        // TODO: implement this
-        /*
+    fn di(&mut self) {
+        self.ime = false;
+        self.ime_scheduled = false;
+    }
+
+    fn ei(&mut self) {
+        if !self.ime {
+            self.ime_scheduled = true;
+        }
+    }
+
+    // The stack lives in memory and grows downwards, words are stored little
+    // endian with the high byte pushed first.
+    fn push_16(&mut self, data: u16) {
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.memory.write(self.registers.sp, (data >> 8) as u8);
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        self.memory.write(self.registers.sp, data as u8);
+    }
+
+    fn pop_16(&mut self) -> u16 {
+        let x = self.memory.read(self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        let y = self.memory.read(self.registers.sp);
+        self.registers.sp = self.registers.sp.wrapping_add(1);
+        (y as u16) << 8 | x as u16
+    }
+
+    fn push(&mut self, register: Register) {
         match register {
-            Register::A => ,
-            Register::B => ,
-            Register::C => ,
-            Register::D => ,
-            Register::E => ,
-            Register::F => ,
-            Register::H => ,
-            Register::L => ,
+            Register::AF | Register::BC | Register::DE | Register::HL => {
+                let data = self.registers.get_16(register);
+                self.push_16(data);
+            },
             _ => panic!("Invalid register provided!"),
         }
-        */
-        None
     }
 
-    fn push(&mut self, register: Register) -> Option<Vec<usize>> {
+    fn pop(&mut self, register: Register) {
         match register {
-            Register::AF => self.stack.push(self.registers.get_af()),
-            Register::BC => self.stack.push(self.registers.get_bc()),
-            Register::DE => self.stack.push(self.registers.get_de()),
-            Register::HL => self.stack.push(self.registers.get_hl()),
+            Register::AF | Register::BC | Register::DE | Register::HL => {
+                let data = self.pop_16();
+                self.registers.set_16(register, data);
+            },
             _ => panic!("Invalid register provided!"),
         }
-        self.registers.sp -= 2;
-        None
     }
 
-    fn call_a16(&mut self) -> Option<Vec<usize>> {
+    fn call(&mut self, address: u16) {
+        let pc = self.registers.pc;
+        self.push_16(pc);
+        self.registers.jump(address);
+    }
+
+    fn call_a16(&mut self) {
+        let data = self.read_16();
+        self.call(data);
+    }
+
+    fn call_cc_a16(&mut self, condition: Condition) {
+        let data = self.read_16();
+        if self.condition(condition) {
+            self.call(data);
+        }
+    }
+
+    fn ret(&mut self) {
+        let address = self.pop_16();
+        self.registers.jump(address);
+    }
+
+    fn ret_cc(&mut self, condition: Condition) {
+        if self.condition(condition) {
+            self.ret();
+        }
+    }
+
+    fn reti(&mut self) {
+        self.ime = true;
+        self.ret();
+    }
+
+    fn rst(&mut self, address: u16) {
+        self.call(address);
+    }
+
+    fn jp_a16(&mut self) {
         let data = self.read_16();
-        let address = self.registers.pc;
-        self.stack.push(address);
         self.registers.jump(data);
-        Some(vec![data as usize])
     }
 
-    fn ld_de_d16(&mut self) -> Option<Vec<usize>> {
+    fn jp_cc_a16(&mut self, condition: Condition) {
         let data = self.read_16();
-        self.registers.set_de(data);
-        Some(vec![data as usize])
+        if self.condition(condition) {
+            self.registers.jump(data);
+        }
+    }
+
+    fn jp_hl(&mut self) {
+        let hl = self.registers.get_hl();
+        self.registers.jump(hl);
     }
 
-    fn ld_hl_d16(&mut self) -> Option<Vec<usize>> {
+    fn jr_8(&mut self) {
+        let n = self.read_8();
+        let signed_n = self.registers.to_signed_byte(n) as isize;
+        self.registers.step(signed_n);
+    }
+
+    fn jr_cc_8(&mut self, condition: Condition) {
+        let n = self.read_8();
+        let signed_n = self.registers.to_signed_byte(n) as isize;
+        if self.condition(condition) {
+            self.registers.step(signed_n);
+        }
+    }
+
+    fn ld_xx_d16(&mut self, register: Register) {
         let data = self.read_16();
-        self.registers.set_hl(data);
-        Some(vec![data as usize])
+        self.registers.set_16(register, data);
+    }
+
+    fn ld_a16_sp(&mut self) {
+        let address = self.read_16();
+        let sp = self.registers.sp;
+        self.memory.write(address, sp as u8);
+        self.memory.write(address.wrapping_add(1), (sp >> 8) as u8);
+    }
+
+    fn ld_sp_hl(&mut self) {
+        self.registers.sp = self.registers.get_hl();
+    }
+
+    fn ld_hl_sp_r8(&mut self) {
+        let n = self.read_8();
+        let result = self.add_sp(n);
+        self.registers.set_hl(result);
+    }
+
+    fn add_sp_r8(&mut self) {
+        let n = self.read_8();
+        self.registers.sp = self.add_sp(n);
+    }
+
+    fn add_sp(&mut self, n: u8) -> u16 {
+        // H and C are computed from the unsigned low byte addition
+        let sp = self.registers.sp;
+        let offset = self.registers.to_signed_byte(n) as i16 as u16;
+        self.registers.set_flag(Flags::Z, false);
+        self.registers.set_flag(Flags::N, false);
+        self.registers.set_flag(Flags::H, (sp & 0x0f) + (n as u16 & 0x0f) > 0x0f);
+        self.registers.set_flag(Flags::C, (sp & 0xff) + n as u16 > 0xff);
+        sp.wrapping_add(offset)
     }
 
-    fn ld_hld_a(&mut self) -> Option<Vec<usize>> {
+    fn ld_hld_a(&mut self) {
         let hl = self.registers.get_hl();
         let a = self.registers.get_a();
-        self.memory.write(hl as usize, a);
+        self.memory.write(hl, a);
         self.registers.dec_hl();
-        None
     }
 
-    fn ld_x_d8(&mut self, register: Register) -> Option<Vec<usize>> {
+    fn ld_hli_a(&mut self) {
+        let hl = self.registers.get_hl();
+        let a = self.registers.get_a();
+        self.memory.write(hl, a);
+        self.registers.inc_hl();
+    }
+
+    fn ld_a_hld(&mut self) {
+        let hl = self.registers.get_hl();
+        let byte = self.memory.read(hl);
+        self.registers.set_a(byte);
+        self.registers.dec_hl();
+    }
+
+    fn ld_a_hli(&mut self) {
+        let hl = self.registers.get_hl();
+        let byte = self.memory.read(hl);
+        self.registers.set_a(byte);
+        self.registers.inc_hl();
+    }
+
+    fn ld_x_d8(&mut self, register: Register) {
         let byte = self.read_8();
-        match register {
-            Register::A => self.registers.set_a(byte),
-            Register::B => self.registers.set_b(byte),
-            Register::C => self.registers.set_c(byte),
-            Register::D => self.registers.set_d(byte),
-            Register::E => self.registers.set_e(byte),
-            Register::F => self.registers.set_f(byte),
-            Register::H => self.registers.set_h(byte),
-            Register::L => self.registers.set_l(byte),
-            _ => panic!("Invalid register provided!"),
-        }
-        Some(vec![byte as usize])
+        self.registers.set_8(register, byte);
     }
 
-    fn ldh_d8_a(&mut self) -> Option<Vec<usize>> {
-        let byte = self.read_8() as usize;
+    fn ld_x_y(&mut self, destination: Register, source: Register) {
+        let byte = self.registers.get_8(source);
+        self.registers.set_8(destination, byte);
+    }
+
+    fn ld_x_hl(&mut self, register: Register) {
+        let hl = self.registers.get_hl();
+        let byte = self.memory.read(hl);
+        self.registers.set_8(register, byte);
+    }
+
+    fn ld_hl_x(&mut self, register: Register) {
+        let hl = self.registers.get_hl();
+        let byte = self.registers.get_8(register);
+        self.memory.write(hl, byte);
+    }
+
+    fn ld_hl_d8(&mut self) {
+        let byte = self.read_8();
+        let hl = self.registers.get_hl();
+        self.memory.write(hl, byte);
+    }
+
+    fn ld_xx_a(&mut self, register: Register) {
+        let address = self.registers.get_16(register);
         let a = self.registers.get_a();
-        self.memory.write((IO.0 as usize) + byte, a);
-        Some(vec![byte])
+        self.memory.write(address, a);
+    }
+
+    fn ld_a_xx(&mut self, register: Register) {
+        let address = self.registers.get_16(register);
+        let byte = self.memory.read(address);
+        self.registers.set_a(byte);
+    }
+
+    fn ld_a16_a(&mut self) {
+        let address = self.read_16();
+        let a = self.registers.get_a();
+        self.memory.write(address, a);
+    }
+
+    fn ld_a_a16(&mut self) {
+        let address = self.read_16();
+        let byte = self.memory.read(address);
+        self.registers.set_a(byte);
+    }
+
+    fn ldh_d8_a(&mut self) {
+        let byte = self.read_8();
+        let a = self.registers.get_a();
+        self.memory.write(IO.0 + byte as u16, a);
+    }
+
+    fn ldh_a_d8(&mut self) {
+        let byte = self.read_8();
+        let data = self.memory.read(IO.0 + byte as u16);
+        self.registers.set_a(data);
     }
 
-    fn ld_ffc_a(&mut self) -> Option<Vec<usize>> {
-        let c = self.registers.get_c() as usize;
+    fn ld_ffc_a(&mut self) {
+        let c = self.registers.get_c();
         let a = self.registers.get_a();
-        self.memory.write((IO.0 as usize) + c, a);
-        None
+        self.memory.write(IO.0 + c as u16, a);
+    }
+
+    fn ld_a_ffc(&mut self) {
+        let c = self.registers.get_c();
+        let byte = self.memory.read(IO.0 + c as u16);
+        self.registers.set_a(byte);
     }
 
-    fn ld_c_a(&mut self) -> Option<Vec<usize>> {
+    fn add_a(&mut self, value: u8, carry: bool) {
         let a = self.registers.get_a();
-        self.registers.set_c(a);
-        None
+        let c = if carry { self.registers.get_flag_c() } else { 0 };
+        let result = a.wrapping_add(value).wrapping_add(c);
+        self.registers.set_flag(Flags::Z, result == 0);
+        self.registers.set_flag(Flags::N, false);
+        self.registers.set_flag(Flags::H, (a & 0x0f) + (value & 0x0f) + c > 0x0f);
+        self.registers.set_flag(Flags::C, a as u16 + value as u16 + c as u16 > 0xff);
+        self.registers.set_a(result);
     }
 
-    fn ld_hl_a(&mut self) -> Option<Vec<usize>> {
-        // TODO: Not so sure about this tbh
+    fn add_a_x(&mut self, register: Register, carry: bool) {
+        let value = self.registers.get_8(register);
+        self.add_a(value, carry);
+    }
+
+    fn add_a_hl(&mut self, carry: bool) {
         let hl = self.registers.get_hl();
+        let value = self.memory.read(hl);
+        self.add_a(value, carry);
+    }
+
+    fn add_a_d8(&mut self, carry: bool) {
+        let byte = self.read_8();
+        self.add_a(byte, carry);
+    }
+
+    fn sub(&mut self, value: u8, carry: bool) -> u8 {
         let a = self.registers.get_a();
-        self.memory.write(hl as usize, a);
-        None
+        let c = if carry { self.registers.get_flag_c() } else { 0 };
+        let result = a.wrapping_sub(value).wrapping_sub(c);
+        self.registers.set_flag(Flags::Z, result == 0);
+        self.registers.set_flag(Flags::N, true);
+        self.registers.set_flag(Flags::H, (a & 0x0f) < (value & 0x0f) + c);
+        self.registers.set_flag(Flags::C, (a as u16) < value as u16 + c as u16);
+        result
+    }
+
+    fn sub_a_x(&mut self, register: Register, carry: bool) {
+        let value = self.registers.get_8(register);
+        let result = self.sub(value, carry);
+        self.registers.set_a(result);
     }
 
-    fn ld_a_d8(&mut self) -> Option<Vec<usize>> {
+    fn sub_a_hl(&mut self, carry: bool) {
+        let hl = self.registers.get_hl();
+        let value = self.memory.read(hl);
+        let result = self.sub(value, carry);
+        self.registers.set_a(result);
+    }
+
+    fn sub_a_d8(&mut self, carry: bool) {
         let byte = self.read_8();
-        self.registers.set_a(byte);
-        Some(vec![byte as usize])
+        let result = self.sub(byte, carry);
+        self.registers.set_a(result);
     }
 
-    fn ld_a_de(&mut self) -> Option<Vec<usize>> {
-        // TODO: check this, i think it's wrong.
-        let c = self.registers.get_c() as usize;
-        let byte = self.memory.read((IO.0 as usize) + c);
-        self.registers.set_a(byte);
-        None
+    fn cp_x(&mut self, register: Register) {
+        let value = self.registers.get_8(register);
+        self.sub(value, false);
     }
 
-    fn xora(&mut self) -> Option<Vec<usize>> {
-        let result = self.registers.get_a() ^ self.registers.get_a();
-        if result == 0 {
-            self.registers.set_flag_z();
-        }
+    fn cp_hl(&mut self) {
+        let hl = self.registers.get_hl();
+        let value = self.memory.read(hl);
+        self.sub(value, false);
+    }
+
+    fn cp_d8(&mut self) {
+        let byte = self.read_8();
+        self.sub(byte, false);
+    }
+
+    fn logic(&mut self, result: u8, half_carry: bool) {
         self.registers.set_a(result);
+        self.registers.set_flag(Flags::Z, result == 0);
+        self.registers.set_flag(Flags::N, false);
+        self.registers.set_flag(Flags::H, half_carry);
+        self.registers.set_flag(Flags::C, false);
+    }
+
+    fn and_x(&mut self, register: Register) {
+        let result = self.registers.get_a() & self.registers.get_8(register);
+        self.logic(result, true);
+    }
+
+    fn and_hl(&mut self) {
+        let hl = self.registers.get_hl();
+        let result = self.registers.get_a() & self.memory.read(hl);
+        self.logic(result, true);
+    }
+
+    fn and_d8(&mut self) {
+        let byte = self.read_8();
+        let result = self.registers.get_a() & byte;
+        self.logic(result, true);
+    }
+
+    fn xor_x(&mut self, register: Register) {
+        let result = self.registers.get_a() ^ self.registers.get_8(register);
+        self.logic(result, false);
+    }
+
+    fn xor_hl(&mut self) {
+        let hl = self.registers.get_hl();
+        let result = self.registers.get_a() ^ self.memory.read(hl);
+        self.logic(result, false);
+    }
+
+    fn xor_d8(&mut self) {
+        let byte = self.read_8();
+        let result = self.registers.get_a() ^ byte;
+        self.logic(result, false);
+    }
+
+    fn or_x(&mut self, register: Register) {
+        let result = self.registers.get_a() | self.registers.get_8(register);
+        self.logic(result, false);
+    }
+
+    fn or_hl(&mut self) {
+        let hl = self.registers.get_hl();
+        let result = self.registers.get_a() | self.memory.read(hl);
+        self.logic(result, false);
+    }
+
+    fn or_d8(&mut self) {
+        let byte = self.read_8();
+        let result = self.registers.get_a() | byte;
+        self.logic(result, false);
+    }
+
+    fn inc(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_add(1);
+        self.registers.set_flag(Flags::Z, result == 0);
+        self.registers.set_flag(Flags::N, false);
+        self.registers.set_flag(Flags::H, value & 0x0f == 0x0f);
+        result
+    }
+
+    fn dec(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_sub(1);
+        self.registers.set_flag(Flags::Z, result == 0);
+        self.registers.set_flag(Flags::N, true);
+        self.registers.set_flag(Flags::H, value & 0x0f == 0x00);
+        result
+    }
+
+    fn inc_x(&mut self, register: Register) {
+        let value = self.registers.get_8(register);
+        let result = self.inc(value);
+        self.registers.set_8(register, result);
+    }
+
+    fn dec_x(&mut self, register: Register) {
+        let value = self.registers.get_8(register);
+        let result = self.dec(value);
+        self.registers.set_8(register, result);
+    }
+
+    fn inc_hl_ind(&mut self) {
+        let hl = self.registers.get_hl();
+        let value = self.memory.read(hl);
+        let result = self.inc(value);
+        self.memory.write(hl, result);
+    }
+
+    fn dec_hl_ind(&mut self) {
+        let hl = self.registers.get_hl();
+        let value = self.memory.read(hl);
+        let result = self.dec(value);
+        self.memory.write(hl, result);
+    }
+
+    fn inc_xx(&mut self, register: Register) {
+        let value = self.registers.get_16(register);
+        self.registers.set_16(register, value.wrapping_add(1));
+    }
+
+    fn dec_xx(&mut self, register: Register) {
+        let value = self.registers.get_16(register);
+        self.registers.set_16(register, value.wrapping_sub(1));
+    }
+
+    fn add_hl_xx(&mut self, register: Register) {
+        let hl = self.registers.get_hl();
+        let value = self.registers.get_16(register);
+        self.registers.set_flag(Flags::N, false);
+        self.registers.set_flag(Flags::H, (hl & 0x0fff) + (value & 0x0fff) > 0x0fff);
+        self.registers.set_flag(Flags::C, hl as u32 + value as u32 > 0xffff);
+        self.registers.set_hl(hl.wrapping_add(value));
+    }
+
+    fn daa(&mut self) {
+        let mut a = self.registers.get_a();
+        let mut carry = self.registers.get_flag_c() == 1;
+        if self.registers.get_flag_n() == 0 {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.registers.get_flag_h() == 1 || (a & 0x0f) > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        } else {
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if self.registers.get_flag_h() == 1 {
+                a = a.wrapping_sub(0x06);
+            }
+        }
+        self.registers.set_a(a);
+        self.registers.set_flag(Flags::Z, a == 0);
+        self.registers.set_flag(Flags::H, false);
+        self.registers.set_flag(Flags::C, carry);
+    }
+
+    fn cpl(&mut self) {
+        let a = self.registers.get_a();
+        self.registers.set_a(!a);
+        self.registers.set_flag_n();
+        self.registers.set_flag_h();
+    }
+
+    fn scf(&mut self) {
         self.registers.clear_flag_n();
         self.registers.clear_flag_h();
-        self.registers.clear_flag_c();
-        None
+        self.registers.set_flag_c();
     }
 
-    fn bit_h(&mut self, bit: u8) -> Option<Vec<usize>> {
-        let h = self.registers.get_h();
-        let n = self.registers.get_bit(h, bit);
-        if n == 0 {
-            self.registers.set_flag_z();
+    fn ccf(&mut self) {
+        let c = self.registers.get_flag_c() == 1;
+        self.registers.clear_flag_n();
+        self.registers.clear_flag_h();
+        self.registers.set_flag(Flags::C, !c);
+    }
+
+    fn rotate_a(&mut self, result: u8, carry: bool) {
+        // unlike their CB counterparts, the accumulator rotates always clear Z
+        self.registers.set_a(result);
+        self.registers.set_flag(Flags::Z, false);
+        self.registers.set_flag(Flags::N, false);
+        self.registers.set_flag(Flags::H, false);
+        self.registers.set_flag(Flags::C, carry);
+    }
+
+    fn rlca(&mut self) {
+        let a = self.registers.get_a();
+        self.rotate_a(a.rotate_left(1), a & 0x80 != 0);
+    }
+
+    fn rrca(&mut self) {
+        let a = self.registers.get_a();
+        self.rotate_a(a.rotate_right(1), a & 0x01 != 0);
+    }
+
+    fn rla(&mut self) {
+        let a = self.registers.get_a();
+        let c = self.registers.get_flag_c();
+        self.rotate_a(a << 1 | c, a & 0x80 != 0);
+    }
+
+    fn rra(&mut self) {
+        let a = self.registers.get_a();
+        let c = self.registers.get_flag_c();
+        self.rotate_a(a >> 1 | c << 7, a & 0x01 != 0);
+    }
+
+    fn prefixed(&mut self, opcode: u8) {
+        match PREFIXED[opcode as usize].instruction {
+            Prefixed::RLC(target) => self.shift(target, |value, _| (value.rotate_left(1), value & 0x80 != 0)),
+            Prefixed::RRC(target) => self.shift(target, |value, _| (value.rotate_right(1), value & 0x01 != 0)),
+            Prefixed::RL(target) => self.shift(target, |value, c| (value << 1 | c, value & 0x80 != 0)),
+            Prefixed::RR(target) => self.shift(target, |value, c| (value >> 1 | c << 7, value & 0x01 != 0)),
+            Prefixed::SLA(target) => self.shift(target, |value, _| (value << 1, value & 0x80 != 0)),
+            Prefixed::SRA(target) => self.shift(target, |value, _| (value >> 1 | value & 0x80, value & 0x01 != 0)),
+            Prefixed::SWAP(target) => self.shift(target, |value, _| (value.rotate_left(4), false)),
+            Prefixed::SRL(target) => self.shift(target, |value, _| (value >> 1, value & 0x01 != 0)),
+            Prefixed::BIT(bit, target) => self.bit(bit, target),
+            Prefixed::RES(bit, target) => {
+                let value = self.read_target(target);
+                let result = self.registers.clear_bit(value, bit);
+                self.write_target(target, result);
+            },
+            Prefixed::SET(bit, target) => {
+                let value = self.read_target(target);
+                let result = self.registers.set_bit(value, bit);
+                self.write_target(target, result);
+            },
+        }
+    }
+
+    fn read_target(&mut self, target: Target) -> u8 {
+        match target {
+            Target::Register(register) => self.registers.get_8(register),
+            Target::HL => {
+                let hl = self.registers.get_hl();
+                self.memory.read(hl)
+            },
+        }
+    }
+
+    fn write_target(&mut self, target: Target, byte: u8) {
+        match target {
+            Target::Register(register) => self.registers.set_8(register, byte),
+            Target::HL => {
+                let hl = self.registers.get_hl();
+                self.memory.write(hl, byte);
+            },
         }
+    }
+
+    // Runs one of the CB rotate/shift operations. `operation` receives the
+    // value and the current carry and returns the result and the new carry.
+    fn shift<F>(&mut self, target: Target, operation: F) where F: Fn(u8, u8) -> (u8, bool) {
+        let value = self.read_target(target);
+        let c = self.registers.get_flag_c();
+        let (result, carry) = operation(value, c);
+        self.registers.set_flag(Flags::Z, result == 0);
+        self.registers.set_flag(Flags::N, false);
+        self.registers.set_flag(Flags::H, false);
+        self.registers.set_flag(Flags::C, carry);
+        self.write_target(target, result);
+    }
+
+    fn bit(&mut self, bit: u8, target: Target) {
+        let value = self.read_target(target);
+        let n = self.registers.get_bit(value, bit);
+        self.registers.set_flag(Flags::Z, n == 0);
         self.registers.clear_flag_n();
         self.registers.set_flag_h();
-        None
     }
+}
 
-    fn jr_nz_8(&mut self) -> Option<Vec<usize>> {
-        let n = self.read_8();
-        let z = self.registers.get_flag_z();
-        let signed_n = self.registers.to_signed_byte(n) as isize;
-        if z == 0 {
-            self.registers.step(signed_n);
+#[cfg(test)]
+pub mod tests {
+    use cartridge::Cartridge;
+    use interrupts::{Interrupt, INTERRUPT_FLAG};
+    use joypad::{Button, JOYPAD};
+    use mbc;
+    use memory::INTERRUPT_ENABLE;
+    use super::CPU;
+    use timer::{TAC, TIMA};
+
+    // A CPU running a 32 KiB ROM-only cartridge with `program` at the entry
+    // point, set up the way the boot ROM leaves things.
+    pub fn cpu(program: &[u8]) -> CPU {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        let mbc = mbc::new(Cartridge::new(rom).unwrap()).unwrap();
+        let mut cpu = CPU::new(mbc, None, false).unwrap();
+        cpu.poke(INTERRUPT_FLAG, 0);
+        cpu
+    }
+
+    // Whether the joypad interrupt was requested since the last call
+    fn joypad_requested(cpu: &mut CPU) -> bool {
+        let requested = cpu.peek(INTERRUPT_FLAG) & Interrupt::Joypad.mask() != 0;
+        cpu.poke(INTERRUPT_FLAG, 0);
+        requested
+    }
+
+    #[test]
+    fn buttons_follow_the_select_lines() {
+        let mut cpu = cpu(&[]);
+
+        // P14 low selects the direction keys
+        cpu.poke(JOYPAD, 0x20);
+        cpu.set_button(Button::Start, true);
+        assert_eq!(cpu.peek(JOYPAD), 0xef);
+        assert!(!joypad_requested(&mut cpu));
+        cpu.set_button(Button::Down, true);
+        assert_eq!(cpu.peek(JOYPAD), 0xe7);
+        assert!(joypad_requested(&mut cpu));
+        // a line going back high doesn't interrupt
+        cpu.set_button(Button::Down, false);
+        assert_eq!(cpu.peek(JOYPAD), 0xef);
+        assert!(!joypad_requested(&mut cpu));
+
+        // P15 low selects the action buttons, selecting a held one pulls its
+        // line low too
+        cpu.poke(JOYPAD, 0x10);
+        assert_eq!(cpu.peek(JOYPAD), 0xd7);
+        assert!(joypad_requested(&mut cpu));
+        cpu.set_button(Button::A, true);
+        assert_eq!(cpu.peek(JOYPAD), 0xd6);
+        assert!(joypad_requested(&mut cpu));
+        cpu.set_button(Button::Right, true);
+        assert_eq!(cpu.peek(JOYPAD), 0xd6);
+        assert!(!joypad_requested(&mut cpu));
+
+        // with both selected the halves share the lines, Right and A are
+        // already holding P10 low
+        cpu.poke(JOYPAD, 0x00);
+        assert_eq!(cpu.peek(JOYPAD), 0xc6);
+        assert!(!joypad_requested(&mut cpu));
+        cpu.set_button(Button::A, false);
+        assert_eq!(cpu.peek(JOYPAD), 0xc6);
+        cpu.set_button(Button::Up, true);
+        assert_eq!(cpu.peek(JOYPAD), 0xc2);
+        assert!(joypad_requested(&mut cpu));
+
+        // nothing selected reads all high
+        cpu.poke(JOYPAD, 0x30);
+        assert_eq!(cpu.peek(JOYPAD), 0xff);
+        assert!(!joypad_requested(&mut cpu));
+    }
+
+    #[test]
+    fn every_button_has_its_own_line() {
+        let buttons = [
+            (Button::Right, 0x20, 0x01), (Button::Left, 0x20, 0x02),
+            (Button::Up, 0x20, 0x04), (Button::Down, 0x20, 0x08),
+            (Button::A, 0x10, 0x01), (Button::B, 0x10, 0x02),
+            (Button::Select, 0x10, 0x04), (Button::Start, 0x10, 0x08),
+        ];
+        let mut cpu = cpu(&[]);
+        for &(button, select, line) in buttons.iter() {
+            cpu.poke(JOYPAD, select);
+            cpu.set_button(button, true);
+            assert_eq!(cpu.peek(JOYPAD), 0xc0 | select | (0x0f & !line));
+            assert!(joypad_requested(&mut cpu));
+            // the other half doesn't see it
+            cpu.poke(JOYPAD, 0x30 & !select);
+            assert_eq!(cpu.peek(JOYPAD) & 0x0f, 0x0f);
+            cpu.set_button(button, false);
         }
-        Some(vec![n as usize])
     }
 
-    fn inc_c(&mut self) -> Option<Vec<usize>> {
-        self.registers.inc_c();
-        // TODO: implement flags
-        None
+    // Runs the first `steps` instructions of `program`.
+    fn run(program: &[u8], steps: usize) -> CPU {
+        let mut cpu = cpu(program);
+        for _ in 0..steps {
+            cpu.step();
+        }
+        cpu
+    }
+
+    // A and F after running all of `program`, one instruction per step
+    fn af(program: &[u8], steps: usize) -> (u8, u8) {
+        let cpu = run(program, steps);
+        (cpu.registers().get_a(), cpu.registers().get_f())
+    }
+
+    #[test]
+    fn daa_after_add() {
+        // LD A,$45; ADD A,$38; DAA
+        assert_eq!(af(&[0x3e, 0x45, 0xc6, 0x38, 0x27], 3), (0x83, 0x00));
+        // LD A,$99; ADD A,$01; DAA wraps to 00 with a carry
+        assert_eq!(af(&[0x3e, 0x99, 0xc6, 0x01, 0x27], 3), (0x00, 0x90));
+        // LD A,$08; ADD A,$08; DAA corrects the half carry
+        assert_eq!(af(&[0x3e, 0x08, 0xc6, 0x08, 0x27], 3), (0x16, 0x00));
+    }
+
+    #[test]
+    fn daa_after_sub() {
+        // LD A,$42; SUB $15; DAA
+        assert_eq!(af(&[0x3e, 0x42, 0xd6, 0x15, 0x27], 3), (0x27, 0x40));
+        // LD A,$10; SUB $20; DAA keeps the borrow
+        assert_eq!(af(&[0x3e, 0x10, 0xd6, 0x20, 0x27], 3), (0x90, 0x50));
+    }
+
+    #[test]
+    fn add_sp_with_negative_offsets() {
+        // LD SP,$0001; ADD SP,-1: H and C come from the unsigned low byte
+        let cpu = run(&[0x31, 0x01, 0x00, 0xe8, 0xff], 2);
+        assert_eq!(cpu.registers().sp, 0x0000);
+        assert_eq!(cpu.registers().get_f(), 0x30);
+
+        // LD SP,$00f0; ADD SP,-16
+        let cpu = run(&[0x31, 0xf0, 0x00, 0xe8, 0xf0], 2);
+        assert_eq!(cpu.registers().sp, 0x00e0);
+        assert_eq!(cpu.registers().get_f(), 0x10);
+
+        // LD SP,$1000; ADD SP,-1 borrows from the high byte without flags
+        let cpu = run(&[0x31, 0x00, 0x10, 0xe8, 0xff], 2);
+        assert_eq!(cpu.registers().sp, 0x0fff);
+        assert_eq!(cpu.registers().get_f(), 0x00);
+    }
+
+    #[test]
+    fn ld_hl_sp_with_negative_offset() {
+        // LD SP,$0005; LD HL,SP-2
+        let cpu = run(&[0x31, 0x05, 0x00, 0xf8, 0xfe], 2);
+        assert_eq!(cpu.registers().get_hl(), 0x0003);
+        assert_eq!(cpu.registers().sp, 0x0005);
+        assert_eq!(cpu.registers().get_f(), 0x30);
+    }
+
+    #[test]
+    fn adc_and_sbc_half_carry_include_the_carry() {
+        // LD A,$0f; SCF; ADC A,$00
+        assert_eq!(af(&[0x3e, 0x0f, 0x37, 0xce, 0x00], 3), (0x10, 0x20));
+        // LD A,$10; SCF; SBC A,$00
+        assert_eq!(af(&[0x3e, 0x10, 0x37, 0xde, 0x00], 3), (0x0f, 0x60));
+        // LD A,$ff; SCF; ADC A,$00 carries all the way out
+        assert_eq!(af(&[0x3e, 0xff, 0x37, 0xce, 0x00], 3), (0x00, 0xb0));
+    }
+
+    #[test]
+    fn rlca_clears_z_unlike_rlc_a() {
+        // LD A,$00; RLCA
+        assert_eq!(af(&[0x3e, 0x00, 0x07], 2), (0x00, 0x00));
+        // LD A,$00; RLC A
+        assert_eq!(af(&[0x3e, 0x00, 0xcb, 0x07], 2), (0x00, 0x80));
+        // LD A,$80; RLCA
+        assert_eq!(af(&[0x3e, 0x80, 0x07], 2), (0x01, 0x10));
+    }
+
+    #[test]
+    fn bit_sets_h_and_keeps_c() {
+        // LD A,$10; SCF; BIT 4,A
+        assert_eq!(af(&[0x3e, 0x10, 0x37, 0xcb, 0x67], 3), (0x10, 0x30));
+        // LD A,$10; SCF; BIT 3,A
+        assert_eq!(af(&[0x3e, 0x10, 0x37, 0xcb, 0x5f], 3), (0x10, 0xb0));
+        // LD A,$10; OR A; BIT 3,A
+        assert_eq!(af(&[0x3e, 0x10, 0xb7, 0xcb, 0x5f], 3), (0x10, 0xa0));
+    }
+
+    #[test]
+    fn swap_exchanges_nibbles_and_clears_c() {
+        // LD A,$f1; SCF; SWAP A
+        assert_eq!(af(&[0x3e, 0xf1, 0x37, 0xcb, 0x37], 3), (0x1f, 0x00));
+        // LD A,$00; SWAP A
+        assert_eq!(af(&[0x3e, 0x00, 0xcb, 0x37], 2), (0x00, 0x80));
+    }
+
+    #[test]
+    fn sra_keeps_bit_7() {
+        // LD A,$81; SRA A
+        assert_eq!(af(&[0x3e, 0x81, 0xcb, 0x2f], 2), (0xc0, 0x10));
+        // LD A,$01; SRA A
+        assert_eq!(af(&[0x3e, 0x01, 0xcb, 0x2f], 2), (0x00, 0x90));
+    }
+
+    #[test]
+    fn prefixed_hl_variants_take_longer() {
+        // LD HL,$c000; LD (HL),$81; RLC (HL); BIT 0,(HL); SET 2,(HL); RLC B
+        let mut cpu = cpu(&[0x21, 0x00, 0xc0, 0x36, 0x81, 0xcb, 0x06, 0xcb, 0x46, 0xcb, 0xd6, 0xcb, 0x00]);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.step(), 16);
+        assert_eq!(cpu.peek(0xc000), 0x03);
+        assert_eq!(cpu.step(), 12);
+        assert_eq!(cpu.registers().get_f() & 0x80, 0x00);
+        assert_eq!(cpu.step(), 16);
+        assert_eq!(cpu.peek(0xc000), 0x07);
+        assert_eq!(cpu.step(), 8);
+    }
+
+    // T-cycles of the first instruction of `program`, run with the flags
+    // the boot ROM leaves behind: Z and C set
+    fn cycles(program: &[u8]) -> u32 {
+        cpu(program).step()
+    }
+
+    #[test]
+    fn conditional_branches_cost_more_when_taken() {
+        // JR NZ / JR Z
+        assert_eq!(cycles(&[0x20, 0x05]), 8);
+        assert_eq!(cycles(&[0x28, 0x05]), 12);
+        // JR NC / JR C
+        assert_eq!(cycles(&[0x30, 0x05]), 8);
+        assert_eq!(cycles(&[0x38, 0x05]), 12);
+        // JP NZ / JP Z
+        assert_eq!(cycles(&[0xc2, 0x00, 0x02]), 12);
+        assert_eq!(cycles(&[0xca, 0x00, 0x02]), 16);
+        // CALL NC / CALL C
+        assert_eq!(cycles(&[0xd4, 0x00, 0x02]), 12);
+        assert_eq!(cycles(&[0xdc, 0x00, 0x02]), 24);
+        // RET NZ / RET Z
+        assert_eq!(cycles(&[0xc0]), 8);
+        assert_eq!(cycles(&[0xc8]), 20);
+    }
+
+    #[test]
+    fn taken_branches_land_on_their_target() {
+        assert_eq!(run(&[0x28, 0x05], 1).registers().pc, 0x0107);
+        assert_eq!(run(&[0x20, 0x05], 1).registers().pc, 0x0102);
+
+        // CALL C,$0200 then RET C at $0200 returns past the call
+        let mut program = vec![0u8; 0x101];
+        program[..3].copy_from_slice(&[0xdc, 0x00, 0x02]);
+        program[0x100] = 0xd8;
+        let mut cpu = run(&program, 1);
+        assert_eq!(cpu.registers().pc, 0x0200);
+        assert_eq!(cpu.step(), 20);
+        assert_eq!(cpu.registers().pc, 0x0103);
+    }
+
+    // A CPU with `interrupt` enabled and already requested
+    fn pending(program: &[u8], interrupt: Interrupt) -> CPU {
+        let mut cpu = cpu(program);
+        cpu.poke(INTERRUPT_ENABLE.0, interrupt.mask());
+        cpu.poke(INTERRUPT_FLAG, interrupt.mask());
+        cpu
+    }
+
+    #[test]
+    fn ei_takes_effect_after_the_next_instruction() {
+        // EI; NOP; NOP
+        let mut cpu = pending(&[0xfb, 0x00, 0x00], Interrupt::VBlank);
+        cpu.step();
+        assert!(!cpu.ime());
+        cpu.step();
+        assert!(cpu.ime());
+        assert_eq!(cpu.registers().pc, 0x0102);
+        assert_eq!(cpu.step(), 20);
+        assert_eq!(cpu.registers().pc, 0x0040);
+
+        // EI; DI never enables interrupts
+        let mut cpu = pending(&[0xfb, 0xf3, 0x00], Interrupt::VBlank);
+        cpu.step();
+        cpu.step();
+        cpu.step();
+        assert!(!cpu.ime());
+        assert_eq!(cpu.registers().pc, 0x0103);
+    }
+
+    #[test]
+    fn halt_with_ime_cleared_reads_the_next_opcode_twice() {
+        // HALT; INC A; NOP
+        let mut cpu = pending(&[0x76, 0x3c, 0x00], Interrupt::Timer);
+        cpu.step();
+        assert!(!cpu.halted());
+        assert_eq!(cpu.registers().pc, 0x0101);
+        cpu.step();
+        assert_eq!(cpu.registers().pc, 0x0101);
+        cpu.step();
+        assert_eq!(cpu.registers().pc, 0x0102);
+        assert_eq!(cpu.registers().get_a(), 0x03);
+
+        // without a pending interrupt it halts, and wakes up without
+        // dispatching once one comes in
+        let mut cpu = run(&[0x76, 0x3c, 0x00], 0);
+        cpu.poke(INTERRUPT_ENABLE.0, Interrupt::Timer.mask());
+        cpu.step();
+        assert!(cpu.halted());
+        assert_eq!(cpu.step(), 4);
+        cpu.poke(INTERRUPT_FLAG, Interrupt::Timer.mask());
+        cpu.step();
+        assert!(!cpu.halted());
+        assert_eq!(cpu.registers().pc, 0x0102);
+        assert_eq!(cpu.registers().get_a(), 0x02);
+    }
+
+    #[test]
+    fn dispatch_pushes_pc_and_jumps_to_the_vector() {
+        // EI; NOP
+        let mut cpu = pending(&[0xfb, 0x00], Interrupt::Timer);
+        // VBlank is requested but not enabled, so it doesn't get in the way
+        cpu.poke(INTERRUPT_FLAG, Interrupt::Timer.mask() | Interrupt::VBlank.mask());
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.step(), 20);
+        assert_eq!(cpu.registers().pc, 0x0050);
+        assert_eq!(cpu.registers().sp, 0xfffc);
+        assert_eq!((cpu.peek(0xfffc), cpu.peek(0xfffd)), (0x02, 0x01));
+        assert_eq!(cpu.peek(INTERRUPT_FLAG), 0xe0 | Interrupt::VBlank.mask());
+        assert!(!cpu.ime());
+    }
+
+    #[test]
+    fn timer_overflow_requests_its_interrupt() {
+        let mut cpu = cpu(&[0x00, 0x00]);
+        cpu.poke(TIMA, 0xff);
+        // 262144 Hz, the divider is about to clock TIMA
+        cpu.poke(TAC, 0x05);
+        cpu.step();
+        assert_eq!(cpu.peek(TIMA), 0x00);
+        assert_eq!(cpu.peek(INTERRUPT_FLAG), 0xe0);
+        cpu.step();
+        assert_eq!(cpu.peek(INTERRUPT_FLAG), 0xe0 | Interrupt::Timer.mask());
     }
 }
@@ -0,0 +1,435 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use cpu::CPU;
+use registers::Register;
+use memory::REGIONS;
+use watchpoint::{Access, Watchpoint};
+
+const HELP: &str = "\
+s, step [n]             execute n instructions (default 1)
+n, next                 step over CALL and RST
+c, continue             run until a breakpoint or watchpoint is hit, or ^C
+b, break [addr]         set a breakpoint, or list them without an address
+d, delete <addr>        remove a breakpoint
+watch [r|w|x...] <region|addr[-end]> [value]
+                        stop on accesses, optionally only of a value
+unwatch <n>             remove the nth watchpoint
+r, regs                 show registers and flags
+x <region|addr> [len]   hexdump a region from memory.rs or an address
+l, list [addr] [n]      disassemble around PC or from an address
+set <reg> <value>       set A-L, AF, BC, DE, HL, SP or PC
+w <addr> <byte>...      write bytes to memory
+q, quit                 exit
+Numbers are hex, with an optional $ or 0x prefix; counts are decimal.";
+
+// Bytes shown when hexdumping an address rather than a region
+const DUMP_LENGTH: usize = 0x80;
+
+// Instructions to run between checks for a ^C
+const INTERRUPT_POLL: u32 = 1024;
+
+// Set by SIGINT while the CPU is running
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// std has no signal handling, so talk to libc directly. Elsewhere ^C keeps
+// killing the process.
+#[cfg(unix)]
+mod sigint {
+    use std::os::raw::c_int;
+    use std::sync::atomic::Ordering;
+    use super::INTERRUPTED;
+
+    const SIGINT: c_int = 2;
+
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+
+    extern "C" fn interrupted(_: c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+
+    // Catches ^C, returns the previous handler for restore.
+    pub fn catch() -> usize {
+        unsafe { signal(SIGINT, interrupted as extern "C" fn(c_int) as usize) }
+    }
+
+    pub fn restore(handler: usize) {
+        unsafe {
+            signal(SIGINT, handler);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sigint {
+    pub fn catch() -> usize {
+        0
+    }
+
+    pub fn restore(_handler: usize) {}
+}
+
+pub struct Debugger {
+    cpu: CPU,
+    breakpoints: BTreeSet<u16>,
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn parse_register(name: &str) -> Option<Register> {
+    match name.to_uppercase().as_str() {
+        "A" => Some(Register::A),
+        "F" => Some(Register::F),
+        "B" => Some(Register::B),
+        "C" => Some(Register::C),
+        "D" => Some(Register::D),
+        "E" => Some(Register::E),
+        "H" => Some(Register::H),
+        "L" => Some(Register::L),
+        "AF" => Some(Register::AF),
+        "BC" => Some(Register::BC),
+        "DE" => Some(Register::DE),
+        "HL" => Some(Register::HL),
+        "SP" => Some(Register::SP),
+        _ => None,
+    }
+}
+
+impl Debugger {
+    pub fn new(cpu: CPU) -> Debugger {
+        Debugger {
+            cpu,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+        let mut last = String::new();
+        self.list(None, 1);
+        loop {
+            print!("(xiu) ");
+            io::stdout().flush().ok();
+            let mut line = String::new();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {},
+            }
+            // an empty line repeats the last command
+            let line = if line.trim().is_empty() { last.clone() } else { line.trim().to_string() };
+            if !self.command(&line) {
+                return;
+            }
+            last = line;
+        }
+    }
+
+    // Runs one command, returns false once the user wants out.
+    fn command(&mut self, line: &str) -> bool {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() {
+            return true;
+        }
+        let args = &words[1..];
+        match words[0] {
+            "s" | "step" => {
+                let count = args.first().and_then(|n| n.parse().ok()).unwrap_or(1);
+                for _ in 0..count {
+                    if !self.step() || self.watch_hit() {
+                        break;
+                    }
+                }
+                self.list(None, 1);
+            },
+            "n" | "next" => self.next(),
+            "c" | "continue" => self.continue_until(None),
+            "b" | "break" => match args.first() {
+                Some(address) => match parse_hex(address) {
+                    Some(address) => {
+                        self.breakpoints.insert(address);
+                    },
+                    None => println!("Invalid address {}", address),
+                },
+                None => {
+                    for address in self.breakpoints.iter() {
+                        println!("${:04x}", address);
+                    }
+                },
+            },
+            "d" | "delete" => match args.first().and_then(|address| parse_hex(address)) {
+                Some(address) => {
+                    if !self.breakpoints.remove(&address) {
+                        println!("No breakpoint at ${:04x}", address);
+                    }
+                },
+                None => println!("Usage: delete <addr>"),
+            },
+            "watch" => self.watch(args),
+            "unwatch" => match args.first().and_then(|n| n.parse().ok()) {
+                Some(index) => {
+                    if self.cpu.remove_watchpoint(index).is_none() {
+                        println!("No watchpoint {}", index);
+                    }
+                },
+                None => println!("Usage: unwatch <n>"),
+            },
+            "r" | "regs" => self.registers(),
+            "x" => self.hexdump(args),
+            "l" | "list" => {
+                let address = args.first().and_then(|address| parse_hex(address));
+                let count = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(10);
+                self.list(address, count);
+            },
+            "set" => self.set(args),
+            "w" => self.write(args),
+            "h" | "help" => println!("{}", HELP),
+            "q" | "quit" => return false,
+            command => println!("Unknown command {}, try help", command),
+        }
+        true
+    }
+
+    // Runs one instruction, returns false once the CPU is locked up.
+    fn step(&mut self) -> bool {
+        if self.cpu.locked_up().is_none() {
+            self.cpu.step();
+        }
+        match self.cpu.locked_up() {
+            Some(illegal) => {
+                // the fetch may have tripped a watchpoint, the lock up is what matters
+                self.cpu.take_watch_hit();
+                println!("{}", illegal);
+                false
+            },
+            None => true,
+        }
+    }
+
+    fn continue_until(&mut self, until: Option<u16>) {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        let handler = sigint::catch();
+        // step off a breakpoint we're sitting on before checking for them
+        if self.step() && !self.watch_hit() {
+            let mut steps = 0u32;
+            loop {
+                let pc = self.cpu.registers().pc;
+                if self.breakpoints.contains(&pc) {
+                    println!("Breakpoint at ${:04x}", pc);
+                    break;
+                }
+                if Some(pc) == until || !self.step() || self.watch_hit() {
+                    break;
+                }
+
+                steps += 1;
+                if steps.is_multiple_of(INTERRUPT_POLL) && INTERRUPTED.load(Ordering::SeqCst) {
+                    println!("Interrupted");
+                    break;
+                }
+            }
+        }
+        sigint::restore(handler);
+        self.list(None, 1);
+    }
+
+    fn next(&mut self) {
+        let pc = self.cpu.registers().pc;
+        let (text, length) = self.cpu.disassemble(pc);
+        if text.starts_with("CALL") || text.starts_with("RST") {
+            self.continue_until(Some(pc.wrapping_add(length)));
+        } else {
+            self.step();
+            self.watch_hit();
+            self.list(None, 1);
+        }
+    }
+
+    fn watch_hit(&mut self) -> bool {
+        match self.cpu.take_watch_hit() {
+            Some(hit) => {
+                println!("Watchpoint: {}", hit);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn watch(&mut self, args: &[&str]) {
+        if args.is_empty() {
+            for (i, watchpoint) in self.cpu.watchpoints().iter().enumerate() {
+                println!("{}: {}", i, watchpoint);
+            }
+            return;
+        }
+        // the access kinds are optional and default to writes
+        let (accesses, args) = match args[0].chars()
+            .map(|kind| match kind {
+                'r' => Some(Access::Read.mask()),
+                'w' => Some(Access::Write.mask()),
+                'x' => Some(Access::Execute.mask()),
+                _ => None,
+            })
+            .try_fold(0, |accesses, kind| kind.map(|kind| accesses | kind))
+        {
+            Some(accesses) if args.len() > 1 => (accesses, &args[1..]),
+            _ => (Access::Write.mask(), args),
+        };
+        let region = REGIONS.iter().find(|&&(name, _)| name.eq_ignore_ascii_case(args[0]));
+        let range = match region {
+            Some(&(_, range)) => Some(range),
+            None => {
+                let mut bounds = args[0].splitn(2, '-').map(parse_hex);
+                match (bounds.next(), bounds.next()) {
+                    (Some(Some(start)), None) => Some((start, start)),
+                    (Some(Some(start)), Some(Some(end))) if start <= end => Some((start, end)),
+                    _ => None,
+                }
+            },
+        };
+        let value = match args.get(1) {
+            Some(value) => match parse_hex(value) {
+                Some(value) if value <= 0xff => Some(value as u8),
+                _ => {
+                    println!("Invalid value {}", value);
+                    return;
+                },
+            },
+            None => None,
+        };
+        match range {
+            Some((start, end)) => self.cpu.add_watchpoint(Watchpoint { start, end, accesses, value }),
+            None => println!("Invalid address or region {}", args[0]),
+        }
+    }
+
+    fn registers(&self) {
+        let registers = self.cpu.registers();
+        println!("A=${:02x} F=${:02x} B=${:02x} C=${:02x} D=${:02x} E=${:02x} H=${:02x} L=${:02x} SP=${:04x} PC=${:04x}",
+            registers.get_a(), registers.get_f(), registers.get_b(), registers.get_c(),
+            registers.get_d(), registers.get_e(), registers.get_h(), registers.get_l(),
+            registers.sp, registers.pc);
+        let f = registers.get_f();
+        let flags: String = "ZNHC".chars().enumerate()
+            .map(|(i, flag)| if f & (0x80 >> i) != 0 { flag } else { '-' })
+            .collect();
+        println!("Flags: {}  IME: {}  Halted: {}", flags, self.cpu.ime() as u8, self.cpu.halted() as u8);
+    }
+
+    fn hexdump(&self, args: &[&str]) {
+        let target = match args.first() {
+            Some(target) => target,
+            None => {
+                println!("Usage: x <region|addr> [len]");
+                return;
+            },
+        };
+        let region = REGIONS.iter().find(|&&(name, _)| name.eq_ignore_ascii_case(target));
+        let (start, mut length) = match (region, parse_hex(target)) {
+            (Some(&(_, (start, end))), _) => (start, (end - start) as usize + 1),
+            (None, Some(address)) => (address, DUMP_LENGTH),
+            (None, None) => {
+                let names: Vec<&str> = REGIONS.iter().map(|&(name, _)| name).collect();
+                println!("Unknown region {}, expected an address or one of {}", target, names.join(", "));
+                return;
+            },
+        };
+        if let Some(n) = args.get(1).and_then(|n| n.parse().ok()) {
+            length = n;
+        }
+        // stop at the top of the address space
+        let length = length.min(0x10000 - start as usize);
+
+        for row in (0..length).step_by(16) {
+            let address = start.wrapping_add(row as u16);
+            let bytes: Vec<u8> = (0..16.min(length - row))
+                .map(|i| self.cpu.peek(address.wrapping_add(i as u16)))
+                .collect();
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let text: String = bytes.iter()
+                .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                .collect();
+            println!("${:04x}: {:<47}  {}", address, hex.join(" "), text);
+        }
+    }
+
+    // Disassembly has no way of knowing where instructions before PC start,
+    // so look for an earlier address that decodes into PC and start there.
+    fn list(&self, address: Option<u16>, count: usize) {
+        let pc = self.cpu.registers().pc;
+        let mut address = match address {
+            Some(address) => address,
+            None => (1..=8u16)
+                .rev()
+                .map(|back| pc.wrapping_sub(back))
+                .find(|&start| {
+                    let mut address = start;
+                    for _ in 0..4 {
+                        if address == pc {
+                            return true;
+                        }
+                        address = address.wrapping_add(self.cpu.disassemble(address).1);
+                    }
+                    address == pc
+                })
+                .filter(|_| count > 1)
+                .unwrap_or(pc),
+        };
+        for _ in 0..count {
+            let (text, length) = self.cpu.disassemble(address);
+            let marker = if address == pc { "=>" } else { "  " };
+            let breakpoint = if self.breakpoints.contains(&address) { "*" } else { " " };
+            println!("{}{} ${:04x}: {}", marker, breakpoint, address, text);
+            address = address.wrapping_add(length);
+        }
+    }
+
+    fn set(&mut self, args: &[&str]) {
+        let (name, value) = match (args.first(), args.get(1).and_then(|value| parse_hex(value))) {
+            (Some(name), Some(value)) => (name, value),
+            _ => {
+                println!("Usage: set <reg> <value>");
+                return;
+            },
+        };
+        let registers = self.cpu.registers_mut();
+        if name.eq_ignore_ascii_case("PC") {
+            registers.jump(value);
+            return;
+        }
+        match parse_register(name) {
+            Some(_) if name.len() == 1 && value > 0xff => println!("Value out of range {}", args[1]),
+            Some(register) if name.len() == 1 => registers.set_8(register, value as u8),
+            Some(register) => registers.set_16(register, value),
+            None => println!("Unknown register {}", name),
+        }
+    }
+
+    fn write(&mut self, args: &[&str]) {
+        let address = match args.first().and_then(|address| parse_hex(address)) {
+            Some(address) => address,
+            None => {
+                println!("Usage: w <addr> <byte>...");
+                return;
+            },
+        };
+        // nothing is written unless every byte is valid
+        let mut bytes = Vec::new();
+        for byte in args[1..].iter() {
+            match parse_hex(byte) {
+                Some(value) if value <= 0xff => bytes.push(value as u8),
+                _ => {
+                    println!("Invalid byte {}", byte);
+                    return;
+                },
+            }
+        }
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.cpu.poke(address.wrapping_add(i as u16), byte);
+        }
+    }
+}
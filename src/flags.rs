@@ -4,3 +4,10 @@ pub enum Flags {
     H = 0x05,
     C = 0x04,
 }
+
+pub enum Condition {
+    NZ,
+    Z,
+    NC,
+    C,
+}
@@ -0,0 +1,341 @@
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use cpu::CPU;
+use registers::Register;
+use watchpoint::{Access, Watchpoint};
+
+// Registers in the order the g and G packets send them: A, F, B, C, D, E,
+// H and L as single bytes, then SP and PC as little-endian words. p and P
+// number them the same way.
+const REGISTERS: [Register; 8] = [
+    Register::A, Register::F, Register::B, Register::C,
+    Register::D, Register::E, Register::H, Register::L,
+];
+const SP: usize = 8;
+const PC: usize = 9;
+
+// Instructions to run between checks for a ^C from GDB
+const INTERRUPT_POLL: u32 = 1024;
+
+const SIGINT:   u8 = 0x02;
+const SIGILL:   u8 = 0x04;
+const SIGTRAP:  u8 = 0x05;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| text.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+fn ok(result: Option<()>) -> String {
+    match result {
+        Some(()) => String::from("OK"),
+        None => String::from("E01"),
+    }
+}
+
+// Splits "addr,length" as used by m, M, Z and z.
+fn parse_range(text: &str) -> Option<(u16, usize)> {
+    let mut parts = text.splitn(2, ',');
+    let address = parse_hex(parts.next()?)?;
+    let length = usize::from_str_radix(parts.next()?, 16).ok()?;
+    Some((address, length))
+}
+
+// Waits for GDB to connect on `address` and serves it until it detaches or
+// kills the target.
+pub fn serve(cpu: CPU, address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("Waiting for GDB on {}", address);
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    GdbStub {
+        cpu,
+        stream,
+        breakpoints: BTreeSet::new(),
+        last_reply: String::new(),
+    }.run()
+}
+
+struct GdbStub {
+    cpu: CPU,
+    stream: TcpStream,
+    breakpoints: BTreeSet<u16>,
+    // resent when GDB NAKs it
+    last_reply: String,
+}
+
+impl GdbStub {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match self.handle(&packet)? {
+                Some(reply) => self.send(&reply)?,
+                None => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8; 1];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // The next $packet#checksum, acknowledged. None once GDB hangs up.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {},
+                Some(b'-') => {
+                    let reply = self.last_reply.clone();
+                    self.write_packet(&reply)?;
+                    continue;
+                },
+                // acks, and ^C while we're already stopped
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0u8; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = String::from_utf8_lossy(&checksum);
+            let actual = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+            if u8::from_str_radix(&expected, 16).ok() != Some(actual) {
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+            self.stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${}#{:02x}", data, checksum)
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        self.last_reply = data.to_string();
+        self.write_packet(data)
+    }
+
+    // The reply to a packet, or None when the session is over.
+    fn handle(&mut self, packet: &str) -> io::Result<Option<String>> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match command {
+            "?" => {
+                let signal = if self.cpu.locked_up().is_some() { SIGILL } else { SIGTRAP };
+                format!("S{:02x}", signal)
+            },
+            "g" => self.read_registers(),
+            "G" => ok(self.write_registers(args)),
+            "p" => usize::from_str_radix(args, 16).ok()
+                .and_then(|n| self.read_register(n))
+                .unwrap_or_else(|| String::from("E01")),
+            "P" => ok(self.write_register(args)),
+            "m" => self.read_memory(args).unwrap_or_else(|| String::from("E01")),
+            "M" => ok(self.write_memory(args)),
+            "c" | "s" => {
+                // an optional address to resume from
+                if let Some(address) = parse_hex(args) {
+                    self.cpu.registers_mut().jump(address);
+                }
+                self.resume(command == "s")?
+            },
+            "Z" => ok(self.set_breakpoint(args, true)),
+            "z" => ok(self.set_breakpoint(args, false)),
+            "H" => String::from("OK"),
+            "q" => self.query(args),
+            "k" => return Ok(None),
+            "D" => {
+                self.send("OK")?;
+                return Ok(None);
+            },
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            String::from("PacketSize=4000")
+        } else if args.starts_with("Attached") {
+            String::from("1")
+        } else if args == "fThreadInfo" {
+            String::from("m1")
+        } else if args == "sThreadInfo" {
+            String::from("l")
+        } else {
+            String::new()
+        }
+    }
+
+    fn read_register(&self, n: usize) -> Option<String> {
+        let registers = self.cpu.registers();
+        match n {
+            0..=7 => Some(hex(&[registers.get_8(REGISTERS[n])])),
+            SP => Some(hex(&registers.sp.to_le_bytes())),
+            PC => Some(hex(&registers.pc.to_le_bytes())),
+            _ => None,
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        (0..=PC).filter_map(|n| self.read_register(n)).collect()
+    }
+
+    fn write_register(&mut self, args: &str) -> Option<()> {
+        let mut parts = args.splitn(2, '=');
+        let n = usize::from_str_radix(parts.next()?, 16).ok()?;
+        let bytes = unhex(parts.next()?)?;
+        let registers = self.cpu.registers_mut();
+        match (n, bytes.len()) {
+            (0..=7, 1) => registers.set_8(REGISTERS[n], bytes[0]),
+            (SP, 2) => registers.sp = u16::from_le_bytes([bytes[0], bytes[1]]),
+            (PC, 2) => registers.jump(u16::from_le_bytes([bytes[0], bytes[1]])),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn write_registers(&mut self, args: &str) -> Option<()> {
+        let bytes = unhex(args)?;
+        if bytes.len() != 12 {
+            return None;
+        }
+        let registers = self.cpu.registers_mut();
+        for (i, &register) in REGISTERS.iter().enumerate() {
+            registers.set_8(register, bytes[i]);
+        }
+        registers.sp = u16::from_le_bytes([bytes[8], bytes[9]]);
+        registers.jump(u16::from_le_bytes([bytes[10], bytes[11]]));
+        Some(())
+    }
+
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (address, length) = parse_range(args)?;
+        let bytes: Vec<u8> = (0..length.min(0x10000))
+            .map(|i| self.cpu.peek(address.wrapping_add(i as u16)))
+            .collect();
+        Some(hex(&bytes))
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<()> {
+        let mut parts = args.splitn(2, ':');
+        let (address, length) = parse_range(parts.next()?)?;
+        let bytes = unhex(parts.next()?)?;
+        if bytes.len() != length {
+            return None;
+        }
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.cpu.poke(address.wrapping_add(i as u16), byte);
+        }
+        Some(())
+    }
+
+    // Z0/Z1 are breakpoints, Z2-Z4 write, read and access watchpoints.
+    // Breakpoints are checked against PC, the ROM is never patched.
+    fn set_breakpoint(&mut self, args: &str, insert: bool) -> Option<()> {
+        let mut parts = args.splitn(2, ',');
+        let kind = parts.next()?;
+        let (address, length) = parse_range(parts.next()?)?;
+        let accesses = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                return Some(());
+            },
+            "2" => Access::Write.mask(),
+            "3" => Access::Read.mask(),
+            "4" => Access::Read.mask() | Access::Write.mask(),
+            _ => return None,
+        };
+        // the range has to fit in the address space, a length of 0 watches
+        // a single byte
+        let end = (address as usize).checked_add(length.max(1) - 1).filter(|&end| end <= 0xffff)?;
+        let watchpoint = Watchpoint {
+            start: address,
+            end: end as u16,
+            accesses,
+            value: None,
+        };
+        if insert {
+            self.cpu.add_watchpoint(watchpoint);
+        } else {
+            let index = self.cpu.watchpoints().iter().position(|&w| w == watchpoint)?;
+            self.cpu.remove_watchpoint(index);
+        }
+        Some(())
+    }
+
+    // Runs until a breakpoint, a watchpoint or a ^C, or a single instruction
+    // when stepping, and returns the stop reply.
+    fn resume(&mut self, step: bool) -> io::Result<String> {
+        self.stream.set_nonblocking(true)?;
+        let mut steps = 0u32;
+        let read_write = Access::Read.mask() | Access::Write.mask();
+        let reply = loop {
+            if self.cpu.locked_up().is_none() {
+                self.cpu.step();
+            }
+            // a locked up CPU never gets anywhere again
+            if self.cpu.locked_up().is_some() {
+                self.cpu.take_watch_hit();
+                break format!("S{:02x}", SIGILL);
+            }
+            if let Some(hit) = self.cpu.take_watch_hit() {
+                let kind = if hit.watchpoint.accesses & read_write == read_write {
+                    "awatch"
+                } else if hit.access == Access::Read {
+                    "rwatch"
+                } else {
+                    "watch"
+                };
+                break format!("T{:02x}{}:{:04x};", SIGTRAP, kind, hit.address);
+            }
+            if step || self.breakpoints.contains(&self.cpu.registers().pc) {
+                break format!("S{:02x}", SIGTRAP);
+            }
+
+            steps += 1;
+            if steps.is_multiple_of(INTERRUPT_POLL) {
+                let mut byte = [0u8; 1];
+                match self.stream.read(&mut byte) {
+                    Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "GDB hung up")),
+                    Ok(_) if byte[0] == 0x03 => break format!("S{:02x}", SIGINT),
+                    Ok(_) => {},
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+                    Err(e) => return Err(e),
+                }
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        Ok(reply)
+    }
+}
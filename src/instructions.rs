@@ -2,120 +2,740 @@
 // http://www.devrs.com/gb/files/GBCPU_Instr.html
 // http://www.devrs.com/gb/files/opcodes.html
 
-use std::u8;
+use std::fmt;
+use registers::Register;
 
-#[allow(non_camel_case_types)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 #[derive(PartialEq)]
 pub enum Instructions {
-    LD_SP_D16,
+    NOP,
+    LD_BC_D16,
+    LD_BC_A,
+    INC_BC,
+    INC_B,
+    DEC_B,
+    LD_B_D8,
+    RLCA,
+    LD_A16_SP,
+    ADD_HL_BC,
+    LD_A_BC,
+    DEC_BC,
+    INC_C,
+    DEC_C,
+    LD_C_D8,
+    RRCA,
+    STOP,
+    LD_DE_D16,
+    LD_DE_A,
+    INC_DE,
+    INC_D,
+    DEC_D,
+    LD_D_D8,
+    RLA,
+    JR_8,
+    ADD_HL_DE,
+    LD_A_DE,
+    DEC_DE,
+    INC_E,
+    DEC_E,
+    LD_E_D8,
+    RRA,
+    JR_NZ_8,
     LD_HL_D16,
+    LD_HLI_A,
+    INC_HL,
+    INC_H,
+    DEC_H,
+    LD_H_D8,
+    DAA,
+    JR_Z_8,
+    ADD_HL_HL,
+    LD_A_HLI,
+    DEC_HL,
+    INC_L,
+    DEC_L,
+    LD_L_D8,
+    CPL,
+    JR_NC_8,
+    LD_SP_D16,
     LD_HLD_A,
+    INC_SP,
+    INC_HL_IND,
+    DEC_HL_IND,
+    LD_HL_D8,
+    SCF,
+    JR_C_8,
+    ADD_HL_SP,
+    LD_A_HLD,
+    DEC_SP,
+    INC_A,
+    DEC_A,
+    LD_A_D8,
+    CCF,
+    LD_B_B,
+    LD_B_C,
+    LD_B_D,
+    LD_B_E,
+    LD_B_H,
+    LD_B_L,
+    LD_B_HL,
+    LD_B_A,
+    LD_C_B,
+    LD_C_C,
+    LD_C_D,
+    LD_C_E,
+    LD_C_H,
+    LD_C_L,
+    LD_C_HL,
+    LD_C_A,
+    LD_D_B,
+    LD_D_C,
+    LD_D_D,
+    LD_D_E,
+    LD_D_H,
+    LD_D_L,
+    LD_D_HL,
+    LD_D_A,
+    LD_E_B,
+    LD_E_C,
+    LD_E_D,
+    LD_E_E,
+    LD_E_H,
+    LD_E_L,
+    LD_E_HL,
+    LD_E_A,
+    LD_H_B,
+    LD_H_C,
+    LD_H_D,
+    LD_H_E,
+    LD_H_H,
+    LD_H_L,
+    LD_H_HL,
+    LD_H_A,
+    LD_L_B,
+    LD_L_C,
+    LD_L_D,
+    LD_L_E,
+    LD_L_H,
+    LD_L_L,
+    LD_L_HL,
+    LD_L_A,
+    LD_HL_B,
+    LD_HL_C,
+    LD_HL_D,
+    LD_HL_E,
+    LD_HL_H,
+    LD_HL_L,
+    HALT,
     LD_HL_A,
+    LD_A_B,
+    LD_A_C,
+    LD_A_D,
+    LD_A_E,
+    LD_A_H,
+    LD_A_L,
+    LD_A_HL,
+    LD_A_A,
+    ADD_A_B,
+    ADD_A_C,
+    ADD_A_D,
+    ADD_A_E,
+    ADD_A_H,
+    ADD_A_L,
+    ADD_A_HL,
+    ADD_A_A,
+    ADC_A_B,
+    ADC_A_C,
+    ADC_A_D,
+    ADC_A_E,
+    ADC_A_H,
+    ADC_A_L,
+    ADC_A_HL,
+    ADC_A_A,
+    SUB_B,
+    SUB_C,
+    SUB_D,
+    SUB_E,
+    SUB_H,
+    SUB_L,
+    SUB_HL,
+    SUB_A,
+    SBC_A_B,
+    SBC_A_C,
+    SBC_A_D,
+    SBC_A_E,
+    SBC_A_H,
+    SBC_A_L,
+    SBC_A_HL,
+    SBC_A_A,
+    AND_B,
+    AND_C,
+    AND_D,
+    AND_E,
+    AND_H,
+    AND_L,
+    AND_HL,
+    AND_A,
+    XOR_B,
+    XOR_C,
+    XOR_D,
+    XOR_E,
+    XOR_H,
+    XOR_L,
+    XOR_HL,
     XOR_A,
+    OR_B,
+    OR_C,
+    OR_D,
+    OR_E,
+    OR_H,
+    OR_L,
+    OR_HL,
+    OR_A,
+    CP_B,
+    CP_C,
+    CP_D,
+    CP_E,
+    CP_H,
+    CP_L,
+    CP_HL,
+    CP_A,
+    RET_NZ,
+    POP_BC,
+    JP_NZ_A16,
+    JP_A16,
+    CALL_NZ_A16,
+    PUSH_BC,
+    ADD_A_D8,
+    RST_00H,
+    RET_Z,
+    RET,
+    JP_Z_A16,
     Prefixed,
-    BIT_7_H,
-    JR_NZ_8,
-    LD_C_D8,
-    LD_A_D8,
-    LD_FFC_A,
-    INC_C,
-    LDH_D8_A,
-    LD_DE_D16,
-    LD_A_DE,
+    CALL_Z_A16,
     CALL_A16,
-    LD_C_A,
-    LD_B_D8,
-    PUSH_BC,
-    RL_C,
+    ADC_A_D8,
+    RST_08H,
+    RET_NC,
+    POP_DE,
+    JP_NC_A16,
+    CALL_NC_A16,
+    PUSH_DE,
+    SUB_D8,
+    RST_10H,
+    RET_C,
+    RETI,
+    JP_C_A16,
+    CALL_C_A16,
+    SBC_A_D8,
+    RST_18H,
+    LDH_D8_A,
+    POP_HL,
+    LD_FFC_A,
+    PUSH_HL,
+    AND_D8,
+    RST_20H,
+    ADD_SP_R8,
+    JP_HL,
+    LD_A16_A,
+    XOR_D8,
+    RST_28H,
+    LDH_A_D8,
+    POP_AF,
+    LD_A_FFC,
+    DI,
+    PUSH_AF,
+    OR_D8,
+    RST_30H,
+    LD_HL_SP_R8,
+    LD_SP_HL,
+    LD_A_A16,
+    EI,
+    CP_D8,
+    RST_38H,
     Unknown
 }
 
-pub static INSTRUCTIONS: [(u8, &'static str, &'static str, Instructions); 18] = [
-    (0xcb, "", "", Instructions::Prefixed),
-    (0x21, "LD HL, d16", "LD HL, ${}", Instructions::LD_HL_D16),
-    (0x31, "LD SP, d16", "LD SP, ${}", Instructions::LD_SP_D16),
-    (0x32, "LD (HL-), A", "LD (HL-), A", Instructions::LD_HLD_A),
-    (0xaf, "XOR A", "XOR A", Instructions::XOR_A),
-    (0x20, "JR NZ, r8", "JR NZ, ${}", Instructions::JR_NZ_8),
-    (0x0e, "LD C, d8", "LD C, ${}", Instructions::LD_C_D8),
-    (0x3e, "LD A, d8", "LD A, ${}", Instructions::LD_A_D8),
-    (0xe2, "LD ($FF00+C), A", "LD ($FF00+C), A", Instructions::LD_FFC_A),
-    (0x0c, "INC C", "INC C", Instructions::INC_C),
-    (0x77, "LD (HL), A ", "LD (HL), A", Instructions::LD_HL_A),
-    (0xe0, "LD ($FF00+d8), A", "LD ($FF00+${}), A", Instructions::LDH_D8_A),
-    (0x11, "LD DE, d16", "LD DE, ${}", Instructions::LD_DE_D16),
-    (0x1a, "LD A, (DE)", "LD A, (DE)", Instructions::LD_A_DE),
-    (0xcd, "CALL a16", "CALL ${}", Instructions::CALL_A16),
-    (0x4f, "LD C, A", "LD C, A", Instructions::LD_C_A),
-    (0x06, "LD B, d8", "LD B, ${}", Instructions::LD_B_D8),
-    (0xc5, "PUSH BC", "PUSH BC", Instructions::PUSH_BC)
-];
+// How the bytes following an opcode are interpreted. The mnemonic carries a
+// matching placeholder (d8, d16, a8, a16 or r8) that the disassembler fills in.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Operand {
+    None,
+    D8,
+    D16,
+    A8,
+    A16,
+    R8,
+    S8,
+}
 
-pub static PREFIXED: [(u8, &'static str, &'static str, Instructions); 2] = [
-    (0x7c, "BIT 7, H", "BIT 7, H", Instructions::BIT_7_H),
-    (0x11, "RL C", "RL C", Instructions::RL_C)
-];
+pub struct Instruction {
+    pub mnemonic: &'static str,
+    pub operand: Operand,
+    pub length: u8,
+    pub cycles: u8,
+    // T-cycles spent when a conditional jump, call or return is taken
+    pub cycles_taken: u8,
+    pub instruction: Instructions,
+}
 
-fn find_instruction(instr: u8) -> &'static (u8, &'static str, &'static str, Instructions) {
-    for instruction in INSTRUCTIONS.iter() {
-        if instr == instruction.0 {
-            return instruction;
-        }
+// (HL) is the only memory operand on the CB page, every other target is a register.
+#[derive(Clone, Copy)]
+pub enum Target {
+    Register(Register),
+    HL,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Target::Register(Register::A) => "A",
+            Target::Register(Register::B) => "B",
+            Target::Register(Register::C) => "C",
+            Target::Register(Register::D) => "D",
+            Target::Register(Register::E) => "E",
+            Target::Register(Register::H) => "H",
+            Target::Register(Register::L) => "L",
+            Target::Register(_) => unreachable!("the CB page only targets 8 bit registers"),
+            Target::HL => "(HL)",
+        };
+        f.write_str(name)
     }
-    &(0x00, "", "", Instructions::Unknown)
 }
 
-fn find_prefixed_instruction(instr: u8) -> &'static (u8, &'static str, &'static str, Instructions) {
-    for instruction in PREFIXED.iter() {
-        if instr == instruction.0 {
-            return instruction;
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
+#[derive(Clone, Copy)]
+pub enum Prefixed {
+    RLC(Target),
+    RRC(Target),
+    RL(Target),
+    RR(Target),
+    SLA(Target),
+    SRA(Target),
+    SWAP(Target),
+    SRL(Target),
+    BIT(u8, Target),
+    RES(u8, Target),
+    SET(u8, Target),
+}
+
+impl fmt::Display for Prefixed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Prefixed::RLC(target) => write!(f, "RLC {}", target),
+            Prefixed::RRC(target) => write!(f, "RRC {}", target),
+            Prefixed::RL(target) => write!(f, "RL {}", target),
+            Prefixed::RR(target) => write!(f, "RR {}", target),
+            Prefixed::SLA(target) => write!(f, "SLA {}", target),
+            Prefixed::SRA(target) => write!(f, "SRA {}", target),
+            Prefixed::SWAP(target) => write!(f, "SWAP {}", target),
+            Prefixed::SRL(target) => write!(f, "SRL {}", target),
+            Prefixed::BIT(bit, target) => write!(f, "BIT {}, {}", bit, target),
+            Prefixed::RES(bit, target) => write!(f, "RES {}, {}", bit, target),
+            Prefixed::SET(bit, target) => write!(f, "SET {}, {}", bit, target),
         }
     }
-    &(0x00, "", "", Instructions::Unknown)
 }
 
-pub fn get_assembly(instr: u8) -> String {
-    let i = find_instruction(instr);
-    String::from(i.1)
+#[derive(Clone, Copy)]
+pub struct PrefixedInstruction {
+    // including the fetch of the 0xcb prefix
+    pub cycles: u8,
+    pub instruction: Prefixed,
 }
 
-pub fn get_prefixed_assembly(instr: u8) -> String {
-    let i = find_prefixed_instruction(instr);
-    String::from(i.1)
+const fn entry(mnemonic: &'static str, operand: Operand, length: u8, cycles: u8, cycles_taken: u8, instruction: Instructions) -> Instruction {
+    Instruction { mnemonic, operand, length, cycles, cycles_taken, instruction }
 }
 
-pub fn get_debug(instr: u8, data: Vec<usize>) -> String {
-    let i = find_instruction(instr);
-    let mut val = String::from(i.2);
-    for d in data {
-        if d <= (u8::MAX as usize) {
-            val = val.replacen("{}", &format!("{:02x}", d), 1);
-        } else {
-            val = val.replacen("{}", &format!("{:04x}", d), 1);
-        }
-    }
-    val
+pub static INSTRUCTIONS: [Instruction; 256] = [
+    /* 0x00 */ entry("NOP", Operand::None, 1, 4, 4, Instructions::NOP),
+    /* 0x01 */ entry("LD BC, d16", Operand::D16, 3, 12, 12, Instructions::LD_BC_D16),
+    /* 0x02 */ entry("LD (BC), A", Operand::None, 1, 8, 8, Instructions::LD_BC_A),
+    /* 0x03 */ entry("INC BC", Operand::None, 1, 8, 8, Instructions::INC_BC),
+    /* 0x04 */ entry("INC B", Operand::None, 1, 4, 4, Instructions::INC_B),
+    /* 0x05 */ entry("DEC B", Operand::None, 1, 4, 4, Instructions::DEC_B),
+    /* 0x06 */ entry("LD B, d8", Operand::D8, 2, 8, 8, Instructions::LD_B_D8),
+    /* 0x07 */ entry("RLCA", Operand::None, 1, 4, 4, Instructions::RLCA),
+    /* 0x08 */ entry("LD (a16), SP", Operand::A16, 3, 20, 20, Instructions::LD_A16_SP),
+    /* 0x09 */ entry("ADD HL, BC", Operand::None, 1, 8, 8, Instructions::ADD_HL_BC),
+    /* 0x0a */ entry("LD A, (BC)", Operand::None, 1, 8, 8, Instructions::LD_A_BC),
+    /* 0x0b */ entry("DEC BC", Operand::None, 1, 8, 8, Instructions::DEC_BC),
+    /* 0x0c */ entry("INC C", Operand::None, 1, 4, 4, Instructions::INC_C),
+    /* 0x0d */ entry("DEC C", Operand::None, 1, 4, 4, Instructions::DEC_C),
+    /* 0x0e */ entry("LD C, d8", Operand::D8, 2, 8, 8, Instructions::LD_C_D8),
+    /* 0x0f */ entry("RRCA", Operand::None, 1, 4, 4, Instructions::RRCA),
+    /* 0x10 */ entry("STOP", Operand::None, 2, 4, 4, Instructions::STOP),
+    /* 0x11 */ entry("LD DE, d16", Operand::D16, 3, 12, 12, Instructions::LD_DE_D16),
+    /* 0x12 */ entry("LD (DE), A", Operand::None, 1, 8, 8, Instructions::LD_DE_A),
+    /* 0x13 */ entry("INC DE", Operand::None, 1, 8, 8, Instructions::INC_DE),
+    /* 0x14 */ entry("INC D", Operand::None, 1, 4, 4, Instructions::INC_D),
+    /* 0x15 */ entry("DEC D", Operand::None, 1, 4, 4, Instructions::DEC_D),
+    /* 0x16 */ entry("LD D, d8", Operand::D8, 2, 8, 8, Instructions::LD_D_D8),
+    /* 0x17 */ entry("RLA", Operand::None, 1, 4, 4, Instructions::RLA),
+    /* 0x18 */ entry("JR r8", Operand::R8, 2, 12, 12, Instructions::JR_8),
+    /* 0x19 */ entry("ADD HL, DE", Operand::None, 1, 8, 8, Instructions::ADD_HL_DE),
+    /* 0x1a */ entry("LD A, (DE)", Operand::None, 1, 8, 8, Instructions::LD_A_DE),
+    /* 0x1b */ entry("DEC DE", Operand::None, 1, 8, 8, Instructions::DEC_DE),
+    /* 0x1c */ entry("INC E", Operand::None, 1, 4, 4, Instructions::INC_E),
+    /* 0x1d */ entry("DEC E", Operand::None, 1, 4, 4, Instructions::DEC_E),
+    /* 0x1e */ entry("LD E, d8", Operand::D8, 2, 8, 8, Instructions::LD_E_D8),
+    /* 0x1f */ entry("RRA", Operand::None, 1, 4, 4, Instructions::RRA),
+    /* 0x20 */ entry("JR NZ, r8", Operand::R8, 2, 8, 12, Instructions::JR_NZ_8),
+    /* 0x21 */ entry("LD HL, d16", Operand::D16, 3, 12, 12, Instructions::LD_HL_D16),
+    /* 0x22 */ entry("LD (HL+), A", Operand::None, 1, 8, 8, Instructions::LD_HLI_A),
+    /* 0x23 */ entry("INC HL", Operand::None, 1, 8, 8, Instructions::INC_HL),
+    /* 0x24 */ entry("INC H", Operand::None, 1, 4, 4, Instructions::INC_H),
+    /* 0x25 */ entry("DEC H", Operand::None, 1, 4, 4, Instructions::DEC_H),
+    /* 0x26 */ entry("LD H, d8", Operand::D8, 2, 8, 8, Instructions::LD_H_D8),
+    /* 0x27 */ entry("DAA", Operand::None, 1, 4, 4, Instructions::DAA),
+    /* 0x28 */ entry("JR Z, r8", Operand::R8, 2, 8, 12, Instructions::JR_Z_8),
+    /* 0x29 */ entry("ADD HL, HL", Operand::None, 1, 8, 8, Instructions::ADD_HL_HL),
+    /* 0x2a */ entry("LD A, (HL+)", Operand::None, 1, 8, 8, Instructions::LD_A_HLI),
+    /* 0x2b */ entry("DEC HL", Operand::None, 1, 8, 8, Instructions::DEC_HL),
+    /* 0x2c */ entry("INC L", Operand::None, 1, 4, 4, Instructions::INC_L),
+    /* 0x2d */ entry("DEC L", Operand::None, 1, 4, 4, Instructions::DEC_L),
+    /* 0x2e */ entry("LD L, d8", Operand::D8, 2, 8, 8, Instructions::LD_L_D8),
+    /* 0x2f */ entry("CPL", Operand::None, 1, 4, 4, Instructions::CPL),
+    /* 0x30 */ entry("JR NC, r8", Operand::R8, 2, 8, 12, Instructions::JR_NC_8),
+    /* 0x31 */ entry("LD SP, d16", Operand::D16, 3, 12, 12, Instructions::LD_SP_D16),
+    /* 0x32 */ entry("LD (HL-), A", Operand::None, 1, 8, 8, Instructions::LD_HLD_A),
+    /* 0x33 */ entry("INC SP", Operand::None, 1, 8, 8, Instructions::INC_SP),
+    /* 0x34 */ entry("INC (HL)", Operand::None, 1, 12, 12, Instructions::INC_HL_IND),
+    /* 0x35 */ entry("DEC (HL)", Operand::None, 1, 12, 12, Instructions::DEC_HL_IND),
+    /* 0x36 */ entry("LD (HL), d8", Operand::D8, 2, 12, 12, Instructions::LD_HL_D8),
+    /* 0x37 */ entry("SCF", Operand::None, 1, 4, 4, Instructions::SCF),
+    /* 0x38 */ entry("JR C, r8", Operand::R8, 2, 8, 12, Instructions::JR_C_8),
+    /* 0x39 */ entry("ADD HL, SP", Operand::None, 1, 8, 8, Instructions::ADD_HL_SP),
+    /* 0x3a */ entry("LD A, (HL-)", Operand::None, 1, 8, 8, Instructions::LD_A_HLD),
+    /* 0x3b */ entry("DEC SP", Operand::None, 1, 8, 8, Instructions::DEC_SP),
+    /* 0x3c */ entry("INC A", Operand::None, 1, 4, 4, Instructions::INC_A),
+    /* 0x3d */ entry("DEC A", Operand::None, 1, 4, 4, Instructions::DEC_A),
+    /* 0x3e */ entry("LD A, d8", Operand::D8, 2, 8, 8, Instructions::LD_A_D8),
+    /* 0x3f */ entry("CCF", Operand::None, 1, 4, 4, Instructions::CCF),
+    /* 0x40 */ entry("LD B, B", Operand::None, 1, 4, 4, Instructions::LD_B_B),
+    /* 0x41 */ entry("LD B, C", Operand::None, 1, 4, 4, Instructions::LD_B_C),
+    /* 0x42 */ entry("LD B, D", Operand::None, 1, 4, 4, Instructions::LD_B_D),
+    /* 0x43 */ entry("LD B, E", Operand::None, 1, 4, 4, Instructions::LD_B_E),
+    /* 0x44 */ entry("LD B, H", Operand::None, 1, 4, 4, Instructions::LD_B_H),
+    /* 0x45 */ entry("LD B, L", Operand::None, 1, 4, 4, Instructions::LD_B_L),
+    /* 0x46 */ entry("LD B, (HL)", Operand::None, 1, 8, 8, Instructions::LD_B_HL),
+    /* 0x47 */ entry("LD B, A", Operand::None, 1, 4, 4, Instructions::LD_B_A),
+    /* 0x48 */ entry("LD C, B", Operand::None, 1, 4, 4, Instructions::LD_C_B),
+    /* 0x49 */ entry("LD C, C", Operand::None, 1, 4, 4, Instructions::LD_C_C),
+    /* 0x4a */ entry("LD C, D", Operand::None, 1, 4, 4, Instructions::LD_C_D),
+    /* 0x4b */ entry("LD C, E", Operand::None, 1, 4, 4, Instructions::LD_C_E),
+    /* 0x4c */ entry("LD C, H", Operand::None, 1, 4, 4, Instructions::LD_C_H),
+    /* 0x4d */ entry("LD C, L", Operand::None, 1, 4, 4, Instructions::LD_C_L),
+    /* 0x4e */ entry("LD C, (HL)", Operand::None, 1, 8, 8, Instructions::LD_C_HL),
+    /* 0x4f */ entry("LD C, A", Operand::None, 1, 4, 4, Instructions::LD_C_A),
+    /* 0x50 */ entry("LD D, B", Operand::None, 1, 4, 4, Instructions::LD_D_B),
+    /* 0x51 */ entry("LD D, C", Operand::None, 1, 4, 4, Instructions::LD_D_C),
+    /* 0x52 */ entry("LD D, D", Operand::None, 1, 4, 4, Instructions::LD_D_D),
+    /* 0x53 */ entry("LD D, E", Operand::None, 1, 4, 4, Instructions::LD_D_E),
+    /* 0x54 */ entry("LD D, H", Operand::None, 1, 4, 4, Instructions::LD_D_H),
+    /* 0x55 */ entry("LD D, L", Operand::None, 1, 4, 4, Instructions::LD_D_L),
+    /* 0x56 */ entry("LD D, (HL)", Operand::None, 1, 8, 8, Instructions::LD_D_HL),
+    /* 0x57 */ entry("LD D, A", Operand::None, 1, 4, 4, Instructions::LD_D_A),
+    /* 0x58 */ entry("LD E, B", Operand::None, 1, 4, 4, Instructions::LD_E_B),
+    /* 0x59 */ entry("LD E, C", Operand::None, 1, 4, 4, Instructions::LD_E_C),
+    /* 0x5a */ entry("LD E, D", Operand::None, 1, 4, 4, Instructions::LD_E_D),
+    /* 0x5b */ entry("LD E, E", Operand::None, 1, 4, 4, Instructions::LD_E_E),
+    /* 0x5c */ entry("LD E, H", Operand::None, 1, 4, 4, Instructions::LD_E_H),
+    /* 0x5d */ entry("LD E, L", Operand::None, 1, 4, 4, Instructions::LD_E_L),
+    /* 0x5e */ entry("LD E, (HL)", Operand::None, 1, 8, 8, Instructions::LD_E_HL),
+    /* 0x5f */ entry("LD E, A", Operand::None, 1, 4, 4, Instructions::LD_E_A),
+    /* 0x60 */ entry("LD H, B", Operand::None, 1, 4, 4, Instructions::LD_H_B),
+    /* 0x61 */ entry("LD H, C", Operand::None, 1, 4, 4, Instructions::LD_H_C),
+    /* 0x62 */ entry("LD H, D", Operand::None, 1, 4, 4, Instructions::LD_H_D),
+    /* 0x63 */ entry("LD H, E", Operand::None, 1, 4, 4, Instructions::LD_H_E),
+    /* 0x64 */ entry("LD H, H", Operand::None, 1, 4, 4, Instructions::LD_H_H),
+    /* 0x65 */ entry("LD H, L", Operand::None, 1, 4, 4, Instructions::LD_H_L),
+    /* 0x66 */ entry("LD H, (HL)", Operand::None, 1, 8, 8, Instructions::LD_H_HL),
+    /* 0x67 */ entry("LD H, A", Operand::None, 1, 4, 4, Instructions::LD_H_A),
+    /* 0x68 */ entry("LD L, B", Operand::None, 1, 4, 4, Instructions::LD_L_B),
+    /* 0x69 */ entry("LD L, C", Operand::None, 1, 4, 4, Instructions::LD_L_C),
+    /* 0x6a */ entry("LD L, D", Operand::None, 1, 4, 4, Instructions::LD_L_D),
+    /* 0x6b */ entry("LD L, E", Operand::None, 1, 4, 4, Instructions::LD_L_E),
+    /* 0x6c */ entry("LD L, H", Operand::None, 1, 4, 4, Instructions::LD_L_H),
+    /* 0x6d */ entry("LD L, L", Operand::None, 1, 4, 4, Instructions::LD_L_L),
+    /* 0x6e */ entry("LD L, (HL)", Operand::None, 1, 8, 8, Instructions::LD_L_HL),
+    /* 0x6f */ entry("LD L, A", Operand::None, 1, 4, 4, Instructions::LD_L_A),
+    /* 0x70 */ entry("LD (HL), B", Operand::None, 1, 8, 8, Instructions::LD_HL_B),
+    /* 0x71 */ entry("LD (HL), C", Operand::None, 1, 8, 8, Instructions::LD_HL_C),
+    /* 0x72 */ entry("LD (HL), D", Operand::None, 1, 8, 8, Instructions::LD_HL_D),
+    /* 0x73 */ entry("LD (HL), E", Operand::None, 1, 8, 8, Instructions::LD_HL_E),
+    /* 0x74 */ entry("LD (HL), H", Operand::None, 1, 8, 8, Instructions::LD_HL_H),
+    /* 0x75 */ entry("LD (HL), L", Operand::None, 1, 8, 8, Instructions::LD_HL_L),
+    /* 0x76 */ entry("HALT", Operand::None, 1, 4, 4, Instructions::HALT),
+    /* 0x77 */ entry("LD (HL), A", Operand::None, 1, 8, 8, Instructions::LD_HL_A),
+    /* 0x78 */ entry("LD A, B", Operand::None, 1, 4, 4, Instructions::LD_A_B),
+    /* 0x79 */ entry("LD A, C", Operand::None, 1, 4, 4, Instructions::LD_A_C),
+    /* 0x7a */ entry("LD A, D", Operand::None, 1, 4, 4, Instructions::LD_A_D),
+    /* 0x7b */ entry("LD A, E", Operand::None, 1, 4, 4, Instructions::LD_A_E),
+    /* 0x7c */ entry("LD A, H", Operand::None, 1, 4, 4, Instructions::LD_A_H),
+    /* 0x7d */ entry("LD A, L", Operand::None, 1, 4, 4, Instructions::LD_A_L),
+    /* 0x7e */ entry("LD A, (HL)", Operand::None, 1, 8, 8, Instructions::LD_A_HL),
+    /* 0x7f */ entry("LD A, A", Operand::None, 1, 4, 4, Instructions::LD_A_A),
+    /* 0x80 */ entry("ADD A, B", Operand::None, 1, 4, 4, Instructions::ADD_A_B),
+    /* 0x81 */ entry("ADD A, C", Operand::None, 1, 4, 4, Instructions::ADD_A_C),
+    /* 0x82 */ entry("ADD A, D", Operand::None, 1, 4, 4, Instructions::ADD_A_D),
+    /* 0x83 */ entry("ADD A, E", Operand::None, 1, 4, 4, Instructions::ADD_A_E),
+    /* 0x84 */ entry("ADD A, H", Operand::None, 1, 4, 4, Instructions::ADD_A_H),
+    /* 0x85 */ entry("ADD A, L", Operand::None, 1, 4, 4, Instructions::ADD_A_L),
+    /* 0x86 */ entry("ADD A, (HL)", Operand::None, 1, 8, 8, Instructions::ADD_A_HL),
+    /* 0x87 */ entry("ADD A, A", Operand::None, 1, 4, 4, Instructions::ADD_A_A),
+    /* 0x88 */ entry("ADC A, B", Operand::None, 1, 4, 4, Instructions::ADC_A_B),
+    /* 0x89 */ entry("ADC A, C", Operand::None, 1, 4, 4, Instructions::ADC_A_C),
+    /* 0x8a */ entry("ADC A, D", Operand::None, 1, 4, 4, Instructions::ADC_A_D),
+    /* 0x8b */ entry("ADC A, E", Operand::None, 1, 4, 4, Instructions::ADC_A_E),
+    /* 0x8c */ entry("ADC A, H", Operand::None, 1, 4, 4, Instructions::ADC_A_H),
+    /* 0x8d */ entry("ADC A, L", Operand::None, 1, 4, 4, Instructions::ADC_A_L),
+    /* 0x8e */ entry("ADC A, (HL)", Operand::None, 1, 8, 8, Instructions::ADC_A_HL),
+    /* 0x8f */ entry("ADC A, A", Operand::None, 1, 4, 4, Instructions::ADC_A_A),
+    /* 0x90 */ entry("SUB B", Operand::None, 1, 4, 4, Instructions::SUB_B),
+    /* 0x91 */ entry("SUB C", Operand::None, 1, 4, 4, Instructions::SUB_C),
+    /* 0x92 */ entry("SUB D", Operand::None, 1, 4, 4, Instructions::SUB_D),
+    /* 0x93 */ entry("SUB E", Operand::None, 1, 4, 4, Instructions::SUB_E),
+    /* 0x94 */ entry("SUB H", Operand::None, 1, 4, 4, Instructions::SUB_H),
+    /* 0x95 */ entry("SUB L", Operand::None, 1, 4, 4, Instructions::SUB_L),
+    /* 0x96 */ entry("SUB (HL)", Operand::None, 1, 8, 8, Instructions::SUB_HL),
+    /* 0x97 */ entry("SUB A", Operand::None, 1, 4, 4, Instructions::SUB_A),
+    /* 0x98 */ entry("SBC A, B", Operand::None, 1, 4, 4, Instructions::SBC_A_B),
+    /* 0x99 */ entry("SBC A, C", Operand::None, 1, 4, 4, Instructions::SBC_A_C),
+    /* 0x9a */ entry("SBC A, D", Operand::None, 1, 4, 4, Instructions::SBC_A_D),
+    /* 0x9b */ entry("SBC A, E", Operand::None, 1, 4, 4, Instructions::SBC_A_E),
+    /* 0x9c */ entry("SBC A, H", Operand::None, 1, 4, 4, Instructions::SBC_A_H),
+    /* 0x9d */ entry("SBC A, L", Operand::None, 1, 4, 4, Instructions::SBC_A_L),
+    /* 0x9e */ entry("SBC A, (HL)", Operand::None, 1, 8, 8, Instructions::SBC_A_HL),
+    /* 0x9f */ entry("SBC A, A", Operand::None, 1, 4, 4, Instructions::SBC_A_A),
+    /* 0xa0 */ entry("AND B", Operand::None, 1, 4, 4, Instructions::AND_B),
+    /* 0xa1 */ entry("AND C", Operand::None, 1, 4, 4, Instructions::AND_C),
+    /* 0xa2 */ entry("AND D", Operand::None, 1, 4, 4, Instructions::AND_D),
+    /* 0xa3 */ entry("AND E", Operand::None, 1, 4, 4, Instructions::AND_E),
+    /* 0xa4 */ entry("AND H", Operand::None, 1, 4, 4, Instructions::AND_H),
+    /* 0xa5 */ entry("AND L", Operand::None, 1, 4, 4, Instructions::AND_L),
+    /* 0xa6 */ entry("AND (HL)", Operand::None, 1, 8, 8, Instructions::AND_HL),
+    /* 0xa7 */ entry("AND A", Operand::None, 1, 4, 4, Instructions::AND_A),
+    /* 0xa8 */ entry("XOR B", Operand::None, 1, 4, 4, Instructions::XOR_B),
+    /* 0xa9 */ entry("XOR C", Operand::None, 1, 4, 4, Instructions::XOR_C),
+    /* 0xaa */ entry("XOR D", Operand::None, 1, 4, 4, Instructions::XOR_D),
+    /* 0xab */ entry("XOR E", Operand::None, 1, 4, 4, Instructions::XOR_E),
+    /* 0xac */ entry("XOR H", Operand::None, 1, 4, 4, Instructions::XOR_H),
+    /* 0xad */ entry("XOR L", Operand::None, 1, 4, 4, Instructions::XOR_L),
+    /* 0xae */ entry("XOR (HL)", Operand::None, 1, 8, 8, Instructions::XOR_HL),
+    /* 0xaf */ entry("XOR A", Operand::None, 1, 4, 4, Instructions::XOR_A),
+    /* 0xb0 */ entry("OR B", Operand::None, 1, 4, 4, Instructions::OR_B),
+    /* 0xb1 */ entry("OR C", Operand::None, 1, 4, 4, Instructions::OR_C),
+    /* 0xb2 */ entry("OR D", Operand::None, 1, 4, 4, Instructions::OR_D),
+    /* 0xb3 */ entry("OR E", Operand::None, 1, 4, 4, Instructions::OR_E),
+    /* 0xb4 */ entry("OR H", Operand::None, 1, 4, 4, Instructions::OR_H),
+    /* 0xb5 */ entry("OR L", Operand::None, 1, 4, 4, Instructions::OR_L),
+    /* 0xb6 */ entry("OR (HL)", Operand::None, 1, 8, 8, Instructions::OR_HL),
+    /* 0xb7 */ entry("OR A", Operand::None, 1, 4, 4, Instructions::OR_A),
+    /* 0xb8 */ entry("CP B", Operand::None, 1, 4, 4, Instructions::CP_B),
+    /* 0xb9 */ entry("CP C", Operand::None, 1, 4, 4, Instructions::CP_C),
+    /* 0xba */ entry("CP D", Operand::None, 1, 4, 4, Instructions::CP_D),
+    /* 0xbb */ entry("CP E", Operand::None, 1, 4, 4, Instructions::CP_E),
+    /* 0xbc */ entry("CP H", Operand::None, 1, 4, 4, Instructions::CP_H),
+    /* 0xbd */ entry("CP L", Operand::None, 1, 4, 4, Instructions::CP_L),
+    /* 0xbe */ entry("CP (HL)", Operand::None, 1, 8, 8, Instructions::CP_HL),
+    /* 0xbf */ entry("CP A", Operand::None, 1, 4, 4, Instructions::CP_A),
+    /* 0xc0 */ entry("RET NZ", Operand::None, 1, 8, 20, Instructions::RET_NZ),
+    /* 0xc1 */ entry("POP BC", Operand::None, 1, 12, 12, Instructions::POP_BC),
+    /* 0xc2 */ entry("JP NZ, a16", Operand::A16, 3, 12, 16, Instructions::JP_NZ_A16),
+    /* 0xc3 */ entry("JP a16", Operand::A16, 3, 16, 16, Instructions::JP_A16),
+    /* 0xc4 */ entry("CALL NZ, a16", Operand::A16, 3, 12, 24, Instructions::CALL_NZ_A16),
+    /* 0xc5 */ entry("PUSH BC", Operand::None, 1, 16, 16, Instructions::PUSH_BC),
+    /* 0xc6 */ entry("ADD A, d8", Operand::D8, 2, 8, 8, Instructions::ADD_A_D8),
+    /* 0xc7 */ entry("RST 00H", Operand::None, 1, 16, 16, Instructions::RST_00H),
+    /* 0xc8 */ entry("RET Z", Operand::None, 1, 8, 20, Instructions::RET_Z),
+    /* 0xc9 */ entry("RET", Operand::None, 1, 16, 16, Instructions::RET),
+    /* 0xca */ entry("JP Z, a16", Operand::A16, 3, 12, 16, Instructions::JP_Z_A16),
+    /* 0xcb */ entry("PREFIX CB", Operand::None, 2, 4, 4, Instructions::Prefixed),
+    /* 0xcc */ entry("CALL Z, a16", Operand::A16, 3, 12, 24, Instructions::CALL_Z_A16),
+    /* 0xcd */ entry("CALL a16", Operand::A16, 3, 24, 24, Instructions::CALL_A16),
+    /* 0xce */ entry("ADC A, d8", Operand::D8, 2, 8, 8, Instructions::ADC_A_D8),
+    /* 0xcf */ entry("RST 08H", Operand::None, 1, 16, 16, Instructions::RST_08H),
+    /* 0xd0 */ entry("RET NC", Operand::None, 1, 8, 20, Instructions::RET_NC),
+    /* 0xd1 */ entry("POP DE", Operand::None, 1, 12, 12, Instructions::POP_DE),
+    /* 0xd2 */ entry("JP NC, a16", Operand::A16, 3, 12, 16, Instructions::JP_NC_A16),
+    /* 0xd3 */ entry("UNKNOWN", Operand::None, 1, 4, 4, Instructions::Unknown),
+    /* 0xd4 */ entry("CALL NC, a16", Operand::A16, 3, 12, 24, Instructions::CALL_NC_A16),
+    /* 0xd5 */ entry("PUSH DE", Operand::None, 1, 16, 16, Instructions::PUSH_DE),
+    /* 0xd6 */ entry("SUB d8", Operand::D8, 2, 8, 8, Instructions::SUB_D8),
+    /* 0xd7 */ entry("RST 10H", Operand::None, 1, 16, 16, Instructions::RST_10H),
+    /* 0xd8 */ entry("RET C", Operand::None, 1, 8, 20, Instructions::RET_C),
+    /* 0xd9 */ entry("RETI", Operand::None, 1, 16, 16, Instructions::RETI),
+    /* 0xda */ entry("JP C, a16", Operand::A16, 3, 12, 16, Instructions::JP_C_A16),
+    /* 0xdb */ entry("UNKNOWN", Operand::None, 1, 4, 4, Instructions::Unknown),
+    /* 0xdc */ entry("CALL C, a16", Operand::A16, 3, 12, 24, Instructions::CALL_C_A16),
+    /* 0xdd */ entry("UNKNOWN", Operand::None, 1, 4, 4, Instructions::Unknown),
+    /* 0xde */ entry("SBC A, d8", Operand::D8, 2, 8, 8, Instructions::SBC_A_D8),
+    /* 0xdf */ entry("RST 18H", Operand::None, 1, 16, 16, Instructions::RST_18H),
+    /* 0xe0 */ entry("LD ($FF00+a8), A", Operand::A8, 2, 12, 12, Instructions::LDH_D8_A),
+    /* 0xe1 */ entry("POP HL", Operand::None, 1, 12, 12, Instructions::POP_HL),
+    /* 0xe2 */ entry("LD ($FF00+C), A", Operand::None, 1, 8, 8, Instructions::LD_FFC_A),
+    /* 0xe3 */ entry("UNKNOWN", Operand::None, 1, 4, 4, Instructions::Unknown),
+    /* 0xe4 */ entry("UNKNOWN", Operand::None, 1, 4, 4, Instructions::Unknown),
+    /* 0xe5 */ entry("PUSH HL", Operand::None, 1, 16, 16, Instructions::PUSH_HL),
+    /* 0xe6 */ entry("AND d8", Operand::D8, 2, 8, 8, Instructions::AND_D8),
+    /* 0xe7 */ entry("RST 20H", Operand::None, 1, 16, 16, Instructions::RST_20H),
+    /* 0xe8 */ entry("ADD SP, r8", Operand::S8, 2, 16, 16, Instructions::ADD_SP_R8),
+    /* 0xe9 */ entry("JP (HL)", Operand::None, 1, 4, 4, Instructions::JP_HL),
+    /* 0xea */ entry("LD (a16), A", Operand::A16, 3, 16, 16, Instructions::LD_A16_A),
+    /* 0xeb */ entry("UNKNOWN", Operand::None, 1, 4, 4, Instructions::Unknown),
+    /* 0xec */ entry("UNKNOWN", Operand::None, 1, 4, 4, Instructions::Unknown),
+    /* 0xed */ entry("UNKNOWN", Operand::None, 1, 4, 4, Instructions::Unknown),
+    /* 0xee */ entry("XOR d8", Operand::D8, 2, 8, 8, Instructions::XOR_D8),
+    /* 0xef */ entry("RST 28H", Operand::None, 1, 16, 16, Instructions::RST_28H),
+    /* 0xf0 */ entry("LD A, ($FF00+a8)", Operand::A8, 2, 12, 12, Instructions::LDH_A_D8),
+    /* 0xf1 */ entry("POP AF", Operand::None, 1, 12, 12, Instructions::POP_AF),
+    /* 0xf2 */ entry("LD A, ($FF00+C)", Operand::None, 1, 8, 8, Instructions::LD_A_FFC),
+    /* 0xf3 */ entry("DI", Operand::None, 1, 4, 4, Instructions::DI),
+    /* 0xf4 */ entry("UNKNOWN", Operand::None, 1, 4, 4, Instructions::Unknown),
+    /* 0xf5 */ entry("PUSH AF", Operand::None, 1, 16, 16, Instructions::PUSH_AF),
+    /* 0xf6 */ entry("OR d8", Operand::D8, 2, 8, 8, Instructions::OR_D8),
+    /* 0xf7 */ entry("RST 30H", Operand::None, 1, 16, 16, Instructions::RST_30H),
+    /* 0xf8 */ entry("LD HL, SP+r8", Operand::S8, 2, 12, 12, Instructions::LD_HL_SP_R8),
+    /* 0xf9 */ entry("LD SP, HL", Operand::None, 1, 8, 8, Instructions::LD_SP_HL),
+    /* 0xfa */ entry("LD A, (a16)", Operand::A16, 3, 16, 16, Instructions::LD_A_A16),
+    /* 0xfb */ entry("EI", Operand::None, 1, 4, 4, Instructions::EI),
+    /* 0xfc */ entry("UNKNOWN", Operand::None, 1, 4, 4, Instructions::Unknown),
+    /* 0xfd */ entry("UNKNOWN", Operand::None, 1, 4, 4, Instructions::Unknown),
+    /* 0xfe */ entry("CP d8", Operand::D8, 2, 8, 8, Instructions::CP_D8),
+    /* 0xff */ entry("RST 38H", Operand::None, 1, 16, 16, Instructions::RST_38H),
+];
+
+// The CB page is a regular grid: bits 7-6 pick the group, bits 5-3 the
+// operation or bit number and bits 2-0 the target, so it's decoded rather
+// than typed out.
+pub static PREFIXED: [PrefixedInstruction; 256] = prefixed_page();
+
+const TARGETS: [Target; 8] = [
+    Target::Register(Register::B), Target::Register(Register::C),
+    Target::Register(Register::D), Target::Register(Register::E),
+    Target::Register(Register::H), Target::Register(Register::L),
+    Target::HL, Target::Register(Register::A),
+];
+
+const fn decode_prefixed(opcode: u8) -> PrefixedInstruction {
+    let target = TARGETS[(opcode & 0x07) as usize];
+    let bit = (opcode >> 3) & 0x07;
+    let instruction = match opcode >> 6 {
+        0x00 => match bit {
+            0 => Prefixed::RLC(target),
+            1 => Prefixed::RRC(target),
+            2 => Prefixed::RL(target),
+            3 => Prefixed::RR(target),
+            4 => Prefixed::SLA(target),
+            5 => Prefixed::SRA(target),
+            6 => Prefixed::SWAP(target),
+            _ => Prefixed::SRL(target),
+        },
+        0x01 => Prefixed::BIT(bit, target),
+        0x02 => Prefixed::RES(bit, target),
+        _ => Prefixed::SET(bit, target),
+    };
+    // (HL) costs a read and a write, BIT only reads it
+    let cycles = match (target, instruction) {
+        (Target::HL, Prefixed::BIT(..)) => 12,
+        (Target::HL, _) => 16,
+        _ => 8,
+    };
+    PrefixedInstruction { cycles, instruction }
 }
 
-pub fn get_prefixed_debug(instr: u8, data: Vec<usize>) -> String {
-    let i = find_prefixed_instruction(instr);
-    let mut val = String::from(i.2);
-    for d in data {
-        if d <= (u8::MAX as usize) {
-            val = val.replacen("{}", &format!("{:02x}", d), 1);
-        } else {
-            val = val.replacen("{}", &format!("{:04x}", d), 1);
-        }
+const fn prefixed_page() -> [PrefixedInstruction; 256] {
+    let mut page = [decode_prefixed(0); 256];
+    let mut opcode = 1;
+    while opcode < 256 {
+        page[opcode] = decode_prefixed(opcode as u8);
+        opcode += 1;
     }
-    val
+    page
 }
 
-pub fn get_instruction(instr: u8) -> &'static Instructions {
-    let i = find_instruction(instr);
-    &i.3
+// Disassembles the instruction at `address`, returning its text and length.
+pub fn disassemble<F>(address: u16, mut read: F) -> (String, u16) where F: FnMut(u16) -> u8 {
+    let opcode = read(address);
+    let instruction = &INSTRUCTIONS[opcode as usize];
+    let operand = address.wrapping_add(1);
+    let text = match instruction.instruction {
+        Instructions::Prefixed => PREFIXED[read(operand) as usize].instruction.to_string(),
+        Instructions::Unknown => format!("DB ${:02x}", opcode),
+        _ => match instruction.operand {
+            Operand::None => String::from(instruction.mnemonic),
+            Operand::D8 => instruction.mnemonic.replacen("d8", &format!("${:02x}", read(operand)), 1),
+            Operand::A8 => instruction.mnemonic.replacen("a8", &format!("${:02x}", read(operand)), 1),
+            Operand::D16 | Operand::A16 => {
+                let data = (read(operand.wrapping_add(1)) as u16) << 8 | read(operand) as u16;
+                let placeholder = if instruction.operand == Operand::D16 { "d16" } else { "a16" };
+                instruction.mnemonic.replacen(placeholder, &format!("${:04x}", data), 1)
+            },
+            Operand::R8 => {
+                let offset = read(operand) as i8 as i16 as u16;
+                let target = address.wrapping_add(2).wrapping_add(offset);
+                instruction.mnemonic.replacen("r8", &format!("${:04x}", target), 1)
+            },
+            Operand::S8 => {
+                let offset = read(operand) as i8 as i16;
+                let sign = if offset < 0 { "-" } else { "+" };
+                let text = format!("{}${:02x}", sign, offset.abs());
+                instruction.mnemonic.replacen("+r8", &text, 1).replacen("r8", &text, 1)
+            },
+        },
+    };
+    (text, instruction.length as u16)
 }
 
-pub fn get_prefixed_instruction(instr: u8) -> &'static Instructions {
-    let i = find_prefixed_instruction(instr);
-    &i.3
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> (String, u16) {
+        disassemble(0x0100, |address| bytes.get((address - 0x0100) as usize).cloned().unwrap_or(0))
+    }
+
+    #[test]
+    fn lengths_match_the_operands() {
+        for opcode in 0..=0xffu8 {
+            let instruction = &INSTRUCTIONS[opcode as usize];
+            let length = match (&instruction.instruction, instruction.operand) {
+                // STOP is followed by a padding byte
+                (&Instructions::Prefixed, _) | (&Instructions::STOP, _) => 2,
+                (_, Operand::None) => 1,
+                (_, Operand::D8) | (_, Operand::A8) | (_, Operand::R8) | (_, Operand::S8) => 2,
+                (_, Operand::D16) | (_, Operand::A16) => 3,
+            };
+            let (text, decoded) = decode(&[opcode, 0x34, 0x12]);
+            assert_eq!(decoded, length, "{:02x} {}", opcode, text);
+            // every placeholder was filled in
+            assert!(!["d8", "d16", "a8", "a16", "r8"].iter().any(|p| text.contains(p)), "{:02x} {}", opcode, text);
+            assert!(instruction.cycles & 0x03 == 0 && instruction.cycles_taken >= instruction.cycles, "{:02x}", opcode);
+        }
+    }
+
+    #[test]
+    fn prefixed_page_follows_the_bit_fields() {
+        let targets = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+        let rotates = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+        for opcode in 0..=0xffu8 {
+            let target = targets[(opcode & 0x07) as usize];
+            let bit = (opcode >> 3) & 0x07;
+            let expected = match opcode >> 6 {
+                0 => format!("{} {}", rotates[bit as usize], target),
+                1 => format!("BIT {}, {}", bit, target),
+                2 => format!("RES {}, {}", bit, target),
+                _ => format!("SET {}, {}", bit, target),
+            };
+            let cycles = match (target, opcode >> 6) {
+                ("(HL)", 1) => 12,
+                ("(HL)", _) => 16,
+                _ => 8,
+            };
+            assert_eq!(decode(&[0xcb, opcode]), (expected, 2));
+            assert_eq!(PREFIXED[opcode as usize].cycles, cycles, "cb {:02x}", opcode);
+        }
+    }
 }
@@ -0,0 +1,29 @@
+pub const INTERRUPT_FLAG:   u16 = 0xff0f;
+
+// Interrupt sources, by bit in IE and IF. Lower bits take priority.
+#[derive(Clone, Copy)]
+pub enum Interrupt {
+    VBlank = 0x00,
+    LcdStat = 0x01,
+    Timer = 0x02,
+    Serial = 0x03,
+    Joypad = 0x04,
+}
+
+pub static INTERRUPTS: [Interrupt; 5] = [
+    Interrupt::VBlank,
+    Interrupt::LcdStat,
+    Interrupt::Timer,
+    Interrupt::Serial,
+    Interrupt::Joypad,
+];
+
+impl Interrupt {
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+
+    pub fn vector(self) -> u16 {
+        0x40 + 8 * self as u16
+    }
+}
@@ -0,0 +1,64 @@
+pub const JOYPAD: u16 = 0xff00;
+
+// Low nibble are the direction keys, high nibble the action buttons, in the
+// order the P10-P13 lines report them.
+#[derive(Clone, Copy)]
+pub enum Button {
+    Right = 0x01,
+    Left = 0x02,
+    Up = 0x04,
+    Down = 0x08,
+    A = 0x10,
+    B = 0x20,
+    Select = 0x40,
+    Start = 0x80,
+}
+
+pub struct Joypad {
+    // P14/P15, a cleared bit selects that half of the buttons
+    select: u8,
+    pressed: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: 0x30,
+            pressed: 0,
+        }
+    }
+
+    // P10-P13 are active low, a line reads 0 while a selected button is held
+    fn lines(&self) -> u8 {
+        let mut low = 0;
+        if self.select & 0x10 == 0 {
+            low |= self.pressed & 0x0f;
+        }
+        if self.select & 0x20 == 0 {
+            low |= self.pressed >> 4;
+        }
+        !low & 0x0f
+    }
+
+    pub fn read(&self) -> u8 {
+        0xc0 | self.select | self.lines()
+    }
+
+    // Both the select lines and the buttons can pull a line low, either way
+    // it requests the joypad interrupt, which is what these return.
+    pub fn write(&mut self, byte: u8) -> bool {
+        let lines = self.lines();
+        self.select = byte & 0x30;
+        lines & !self.lines() != 0
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) -> bool {
+        let lines = self.lines();
+        if pressed {
+            self.pressed |= button as u8;
+        } else {
+            self.pressed &= !(button as u8);
+        }
+        lines & !self.lines() != 0
+    }
+}
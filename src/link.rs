@@ -0,0 +1,195 @@
+#[cfg(unix)]
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use serial::SerialEndpoint;
+
+// Both ends stop and wait for each other at the end of every quantum, so
+// neither can run more than this many T-cycles ahead. One quantum is the
+// time an internally clocked transfer takes.
+const QUANTUM: u32 = 4096;
+
+const HANDSHAKE: &[u8; 4] = b"XIU1";
+
+// Every message is two bytes, a kind and a payload
+const SYNC:     u8 = 0x00;
+const TRANSFER: u8 = 0x01;
+const REPLY:    u8 = 0x02;
+
+trait Stream: Read + Write {}
+impl<T: Read + Write> Stream for T {}
+
+#[cfg(unix)]
+fn hung_up(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset)
+}
+
+// A link cable to another process over TCP or a Unix domain socket.
+//
+// The clock master sends TRANSFER with its SB and blocks until the REPLY
+// carrying the other SB comes back. The other side only ever reads messages
+// while waiting at the end of a quantum, so it sees the transfer at the same
+// emulated time on every run.
+pub struct NetworkLink {
+    stream: Option<Box<dyn Stream>>,
+    cycles: u32,
+    // SYNCs that arrived while we were waiting for a REPLY
+    syncs: u32,
+    // our SB while waiting for the other end to clock a transfer
+    waiting: Option<u8>,
+    inbox: Option<u8>,
+}
+
+impl NetworkLink {
+    // Addresses starting with "unix:" are Unix socket paths, anything else
+    // is a TCP host:port.
+    pub fn listen(address: &str) -> io::Result<NetworkLink> {
+        #[cfg(unix)]
+        {
+            if let Some(path) = address.strip_prefix("unix:") {
+                // a socket left behind by an earlier run would make bind fail,
+                // one nobody answers on any more is safe to remove
+                let socket = fs::symlink_metadata(path).map(|m| m.file_type().is_socket()).unwrap_or(false);
+                if socket && UnixStream::connect(path).is_err() {
+                    fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path)?;
+                // the check above hangs up on whoever is listening, so keep
+                // waiting past peers that leave before the handshake
+                let link = loop {
+                    match listener.accept().and_then(|(stream, _)| NetworkLink::new(Box::new(stream))) {
+                        Err(ref e) if hung_up(e) => continue,
+                        link => break link,
+                    }
+                };
+                // the connection outlives the socket file, which is only
+                // needed to accept it
+                fs::remove_file(path).ok();
+                return link;
+            }
+        }
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        stream.set_nodelay(true)?;
+        NetworkLink::new(Box::new(stream))
+    }
+
+    pub fn connect(address: &str) -> io::Result<NetworkLink> {
+        #[cfg(unix)]
+        {
+            if let Some(path) = address.strip_prefix("unix:") {
+                return NetworkLink::new(Box::new(UnixStream::connect(path)?));
+            }
+        }
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        NetworkLink::new(Box::new(stream))
+    }
+
+    fn new(mut stream: Box<dyn Stream>) -> io::Result<NetworkLink> {
+        stream.write_all(HANDSHAKE)?;
+        let mut handshake = [0u8; 4];
+        stream.read_exact(&mut handshake)?;
+        if &handshake != HANDSHAKE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the other end is not a Xiu link cable"));
+        }
+        Ok(NetworkLink {
+            stream: Some(stream),
+            cycles: 0,
+            syncs: 0,
+            waiting: None,
+            inbox: None,
+        })
+    }
+
+    fn send(&mut self, kind: u8, byte: u8) -> io::Result<()> {
+        match self.stream {
+            Some(ref mut stream) => stream.write_all(&[kind, byte]),
+            None => Ok(()),
+        }
+    }
+
+    // Reads and handles one message, returning the payload of a REPLY.
+    fn handle_message(&mut self) -> io::Result<Option<u8>> {
+        let mut message = [0u8; 2];
+        match self.stream {
+            Some(ref mut stream) => stream.read_exact(&mut message)?,
+            None => return Ok(None),
+        }
+        match message[0] {
+            SYNC => self.syncs += 1,
+            TRANSFER => match self.waiting.take() {
+                Some(sb) => {
+                    self.send(REPLY, sb)?;
+                    self.inbox = Some(message[1]);
+                },
+                None => self.send(REPLY, 0xff)?,
+            },
+            REPLY => return Ok(Some(message[1])),
+            kind => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown message ${:02x}", kind))),
+        }
+        Ok(None)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.send(SYNC, 0)?;
+        while self.syncs == 0 && self.stream.is_some() {
+            if self.handle_message()?.is_some() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected reply"));
+            }
+        }
+        self.syncs = self.syncs.saturating_sub(1);
+        Ok(())
+    }
+
+    fn exchange(&mut self, byte: u8) -> io::Result<u8> {
+        self.send(TRANSFER, byte)?;
+        loop {
+            if let Some(reply) = self.handle_message()? {
+                return Ok(reply);
+            }
+        }
+    }
+
+    // Carries on as if the cable had been pulled.
+    fn disconnect(&mut self, e: io::Error) {
+        eprintln!("Link cable disconnected: {}", e);
+        self.stream = None;
+    }
+}
+
+impl SerialEndpoint for NetworkLink {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        if self.stream.is_none() {
+            return 0xff;
+        }
+        match self.exchange(byte) {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                self.disconnect(e);
+                0xff
+            }
+        }
+    }
+
+    fn publish(&mut self, byte: Option<u8>) {
+        self.waiting = byte;
+    }
+
+    fn receive(&mut self) -> Option<u8> {
+        self.inbox.take()
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles;
+        while self.cycles >= QUANTUM && self.stream.is_some() {
+            self.cycles -= QUANTUM;
+            if let Err(e) = self.sync() {
+                self.disconnect(e);
+            }
+        }
+    }
+}
@@ -1,23 +1,303 @@
-#![feature(untagged_unions)]
-
 mod flags;
 mod registers;
-mod stack;
 mod instructions;
-#[allow(dead_code)] // consts are mostly unused atm
 mod memory;
+mod interrupts;
+mod timer;
+mod apu;
+mod ppu;
+mod joypad;
+mod serial;
+mod link;
+mod watchpoint;
+mod debugger;
+mod gdb;
+mod cartridge;
+mod mbc;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod rtc;
+mod save;
+mod screenshot;
+mod wav;
 mod cpu;
 
 use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+use cartridge::Cartridge;
 use cpu::CPU;
+use ppu::FRAME_CYCLES;
+use wav::WavWriter;
+use serial::CaptureSink;
+use link::NetworkLink;
+use debugger::Debugger;
+use joypad::Button;
+
+// Battery-backed RAM is flushed to disk about once per emulated second
+const SAVE_INTERVAL: u32 = 4_194_304;
+
+const SAMPLE_RATE: u32 = 44_100;
+
+// Scripted button presses are held long enough for games that only poll the
+// joypad every few frames.
+const PRESS_FRAMES: u32 = 5;
+
+const USAGE: &str = "Usage: xiu [-v] [--info] [--debug] [--gdb <port>] [--boot <boot rom>] [--headless] [--frames <n>] [--press <button>@<frame>] [--screenshot <png|ppm>] [--record-audio <wav>] [--serial-capture <file|->] [--link-listen|--link-connect <host:port|unix:path>] <rom>";
+
+fn usage(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    process::exit(1);
+}
+
+// The argument following `option`, which can't be left out
+fn value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> String {
+    args.next().unwrap_or_else(|| usage(&format!("{} needs a value", option)))
+}
+
+fn load(path: &str) -> Vec<u8> {
+    match fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Can't read {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_button(name: &str) -> Option<Button> {
+    match name.to_lowercase().as_str() {
+        "right" => Some(Button::Right),
+        "left" => Some(Button::Left),
+        "up" => Some(Button::Up),
+        "down" => Some(Button::Down),
+        "a" => Some(Button::A),
+        "b" => Some(Button::B),
+        "select" => Some(Button::Select),
+        "start" => Some(Button::Start),
+        _ => None,
+    }
+}
+
+// "start@60" holds Start down once 60 frames have run
+fn parse_press(text: &str) -> Option<(Button, u32)> {
+    let mut parts = text.splitn(2, '@');
+    let button = parse_button(parts.next()?)?;
+    let frame = parts.next()?.parse().ok()?;
+    Some((button, frame))
+}
+
+// Presses and releases the scripted buttons due at `frame`.
+fn press_buttons(cpu: &mut CPU, presses: &[(Button, u32)], frame: u32) {
+    for &(button, start) in presses.iter() {
+        if frame == start {
+            cpu.set_button(button, true);
+        } else if frame == start + PRESS_FRAMES {
+            cpu.set_button(button, false);
+        }
+    }
+}
 
 fn main() {
-    let args: Vec<_> = env::args().collect();
+    let mut rom = None;
+    let mut boot_rom = None;
     let mut verbose = false;
-    if args.contains(&String::from("-v")) {
-        verbose = true;
+    let mut info = false;
+    let mut debug = false;
+    let mut gdb_port = None;
+    let mut frames = None;
+    let mut presses = Vec::new();
+    let mut screenshot = None;
+    let mut record_audio = None;
+    let mut serial_capture = None;
+    let mut link_listen = None;
+    let mut link_connect = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-v" => verbose = true,
+            "--boot" => boot_rom = Some(value(&mut args, "--boot")),
+            "--info" => info = true,
+            "--debug" => debug = true,
+            "--gdb" => gdb_port = Some(value(&mut args, "--gdb")),
+            // there is no window to open yet, so headless is the only mode
+            "--headless" => {},
+            "--frames" => frames = Some(value(&mut args, "--frames")),
+            "--press" => {
+                let text = value(&mut args, "--press");
+                match parse_press(&text) {
+                    Some(press) => presses.push(press),
+                    None => usage(&format!("--press expects <button>@<frame>, got {:?}", text)),
+                }
+            },
+            "--screenshot" => screenshot = Some(value(&mut args, "--screenshot")),
+            "--record-audio" => record_audio = Some(value(&mut args, "--record-audio")),
+            "--serial-capture" => serial_capture = Some(value(&mut args, "--serial-capture")),
+            "--link-listen" => link_listen = Some(value(&mut args, "--link-listen")),
+            "--link-connect" => link_connect = Some(value(&mut args, "--link-connect")),
+            _ if arg.starts_with('-') => usage(&format!("unknown option {}", arg)),
+            _ if rom.is_some() => usage(&format!("more than one ROM given: {}", arg)),
+            _ => rom = Some(arg),
+        }
+    }
+    let rom = rom.unwrap_or_else(|| usage("no ROM given"));
+    let frames = match frames {
+        Some(n) => match n.parse::<u32>() {
+            Ok(n) if n > 0 => Some(n),
+            _ => usage(&format!("--frames expects a positive number, got {:?}", n)),
+        },
+        None => None,
+    };
+    // without a frame count these would run forever and never be written
+    if frames.is_none() {
+        if screenshot.is_some() {
+            usage("--screenshot needs --frames");
+        }
+        if record_audio.is_some() {
+            usage("--record-audio needs --frames");
+        }
+    }
+
+    let cartridge = match Cartridge::new(load(&rom)) {
+        Ok(cartridge) => cartridge,
+        Err(e) => {
+            eprintln!("Invalid ROM {}: {}", rom, e);
+            process::exit(1);
+        }
+    };
+    if info {
+        cartridge.dump();
+        return;
+    }
+    let has_battery = cartridge.has_battery();
+    let mbc = match mbc::new(cartridge) {
+        Ok(mbc) => mbc,
+        Err(e) => {
+            eprintln!("Can't run {}: {}", rom, e);
+            process::exit(1);
+        }
+    };
+    let mut cpu = match CPU::new(mbc, boot_rom.as_ref().map(|path| load(path)), verbose) {
+        Ok(cpu) => cpu,
+        Err(e) => {
+            eprintln!("Can't use {}: {}", boot_rom.unwrap_or_default(), e);
+            process::exit(1);
+        }
+    };
+    if has_battery {
+        cpu.attach_save(Path::new(&rom).with_extension("sav"));
+    }
+    if let Some(path) = serial_capture {
+        // "-" captures to stdout
+        let output: Box<dyn Write> = if path == "-" {
+            Box::new(io::stdout())
+        } else {
+            match File::create(&path) {
+                Ok(file) => Box::new(file),
+                Err(e) => {
+                    eprintln!("Can't write {}: {}", path, e);
+                    process::exit(1);
+                }
+            }
+        };
+        cpu.connect_serial(Box::new(CaptureSink::new(output)));
+    }
+    let link = match (link_listen, link_connect) {
+        (Some(address), _) => Some((NetworkLink::listen(&address), address)),
+        (None, Some(address)) => Some((NetworkLink::connect(&address), address)),
+        (None, None) => None,
+    };
+    if let Some((link, address)) = link {
+        match link {
+            Ok(link) => cpu.connect_serial(Box::new(link)),
+            Err(e) => {
+                eprintln!("Can't link with {}: {}", address, e);
+                process::exit(1);
+            }
+        }
+    }
+    if debug {
+        Debugger::new(cpu).run();
+        return;
+    }
+    if let Some(port) = gdb_port {
+        if let Err(e) = gdb::serve(cpu, &format!("127.0.0.1:{}", port)) {
+            eprintln!("GDB stub failed: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    let mut recording = record_audio.map(|path| {
+        match WavWriter::create(Path::new(&path), SAMPLE_RATE) {
+            Ok(wav) => (path, wav),
+            Err(e) => {
+                eprintln!("Can't write {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    });
+    if recording.is_some() {
+        cpu.enable_audio(SAMPLE_RATE);
     }
 
-    let mut cpu = CPU::new(args.get(1).unwrap().to_string(), verbose);
-    cpu.run();
+    let mut cycles = 0;
+    let mut frame_cycles = 0;
+    let mut frame = 0;
+    let mut rumble = false;
+    press_buttons(&mut cpu, &presses, frame);
+    loop {
+        let step = cpu.step();
+        if let Some(illegal) = cpu.locked_up() {
+            eprintln!("CPU locked up on {}", illegal);
+            break;
+        }
+        cycles += step;
+        if cycles >= SAVE_INTERVAL {
+            cycles -= SAVE_INTERVAL;
+            cpu.flush_save();
+        }
+
+        // with the LCD off there is no VBlank, keep counting frames by time
+        frame_cycles += step;
+        if cpu.frame_ready() || frame_cycles > FRAME_CYCLES {
+            frame_cycles = 0;
+            frame += 1;
+            press_buttons(&mut cpu, &presses, frame);
+            if verbose && cpu.rumble_active() != rumble {
+                rumble = !rumble;
+                eprintln!("Rumble {} at frame {}", if rumble { "on" } else { "off" }, frame);
+            }
+            if let Some((ref path, ref mut wav)) = recording {
+                if let Err(e) = wav.write(&cpu.drain_audio()) {
+                    eprintln!("Can't write {}: {}", path, e);
+                    process::exit(1);
+                }
+            }
+            if Some(frame) == frames {
+                break;
+            }
+        }
+    }
+
+    if let Some(path) = screenshot {
+        if let Err(e) = screenshot::save(Path::new(&path), cpu.framebuffer()) {
+            eprintln!("Can't write {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+    if let Some((path, mut wav)) = recording {
+        if let Err(e) = wav.write(&cpu.drain_audio()).and_then(|_| wav.finish()) {
+            eprintln!("Can't write {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+    cpu.flush_save();
+    if cpu.locked_up().is_some() {
+        process::exit(1);
+    }
 }
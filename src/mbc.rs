@@ -0,0 +1,125 @@
+use cartridge::{Cartridge, CartridgeError, Mapper};
+use mbc1::MBC1;
+use mbc2::MBC2;
+use mbc3::MBC3;
+use mbc5::MBC5;
+use rtc::Rtc;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+// A memory bank controller sits between the bus and the cartridge. ROM
+// addresses are absolute (0x0000-0x7fff), RAM addresses are relative to the
+// start of EXT_RAM.
+pub trait MemoryBankController {
+    fn read_rom(&self, address: u16) -> u8;
+    fn write_rom(&mut self, address: u16, byte: u8);
+    fn read_ram(&self, address: u16) -> u8;
+    fn write_ram(&mut self, address: u16, byte: u8);
+
+    // The external RAM as a whole, for persisting battery-backed saves
+    fn ram(&self) -> &[u8];
+    fn ram_mut(&mut self) -> &mut [u8];
+
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+
+    // Whether the game is running the rumble motor, if the cartridge has one
+    fn rumble_active(&self) -> bool {
+        false
+    }
+}
+
+pub fn new(cartridge: Cartridge) -> Result<Box<dyn MemoryBankController>, CartridgeError> {
+    match cartridge.mapper() {
+        Mapper::None => Ok(Box::new(RomOnly::new(cartridge))),
+        Mapper::MBC1 => Ok(Box::new(MBC1::new(cartridge))),
+        Mapper::MBC2 => Ok(Box::new(MBC2::new(cartridge))),
+        Mapper::MBC3 => Ok(Box::new(MBC3::new(cartridge))),
+        Mapper::MBC5 => Ok(Box::new(MBC5::new(cartridge))),
+        _ => Err(CartridgeError::UnsupportedMapper(cartridge.cartridge_type)),
+    }
+}
+
+// Banks numbers wrap around the number of banks actually present, so a
+// bank register wider than the cartridge mirrors its contents.
+fn bank_address(length: usize, bank_size: usize, bank: usize, offset: usize) -> usize {
+    let banks = length.div_ceil(bank_size);
+    ((bank % banks) * bank_size + offset) % length
+}
+
+pub fn read_bank(data: &[u8], bank_size: usize, bank: usize, offset: usize) -> u8 {
+    if data.is_empty() {
+        return 0xff;
+    }
+    data[bank_address(data.len(), bank_size, bank, offset)]
+}
+
+pub fn write_bank(data: &mut [u8], bank_size: usize, bank: usize, offset: usize, byte: u8) {
+    if data.is_empty() {
+        return;
+    }
+    data[bank_address(data.len(), bank_size, bank, offset)] = byte;
+}
+
+// 32 KiB cartridges without a controller, optionally with up to 8 KiB of RAM.
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn new(cartridge: Cartridge) -> RomOnly {
+        RomOnly {
+            ram: vec![0u8; cartridge.ram_size],
+            rom: cartridge.rom,
+        }
+    }
+}
+
+impl MemoryBankController for RomOnly {
+    fn read_rom(&self, address: u16) -> u8 {
+        *self.rom.get(address as usize).unwrap_or(&0xff)
+    }
+
+    fn write_rom(&mut self, _address: u16, _byte: u8) {}
+
+    fn read_ram(&self, address: u16) -> u8 {
+        *self.ram.get(address as usize).unwrap_or(&0xff)
+    }
+
+    fn write_ram(&mut self, address: u16, byte: u8) {
+        if let Some(x) = self.ram.get_mut(address as usize) {
+            *x = byte;
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use cartridge::Cartridge;
+    use super::ROM_BANK_SIZE;
+
+    // A cartridge of the given type and header size codes whose ROM banks
+    // start with their own bank number, low byte first.
+    pub fn cartridge(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Cartridge {
+        let mut rom = vec![0u8; 0x8000 << rom_size];
+        for (bank, data) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            data[0] = bank as u8;
+            data[1] = (bank >> 8) as u8;
+        }
+        rom[0x0147] = cartridge_type;
+        rom[0x0148] = rom_size;
+        rom[0x0149] = ram_size;
+        Cartridge::new(rom).unwrap()
+    }
+}
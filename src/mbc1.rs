@@ -0,0 +1,138 @@
+use cartridge::Cartridge;
+use mbc::{MemoryBankController, ROM_BANK_SIZE, RAM_BANK_SIZE, read_bank, write_bank};
+
+pub struct MBC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    // 5 bit register, 0 is treated as 1
+    rom_bank: u8,
+    // 2 bit register, either the RAM bank or bits 5-6 of the ROM bank
+    bank_2: u8,
+    // mode 1 applies bank_2 to the RAM and to 0x0000-0x3fff as well
+    mode: u8,
+}
+
+impl MBC1 {
+    pub fn new(cartridge: Cartridge) -> MBC1 {
+        MBC1 {
+            ram: vec![0u8; cartridge.ram_size],
+            rom: cartridge.rom,
+            ram_enabled: false,
+            rom_bank: 1,
+            bank_2: 0,
+            mode: 0,
+        }
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.mode == 1 {
+            self.bank_2 as usize
+        } else {
+            0
+        }
+    }
+}
+
+impl MemoryBankController for MBC1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        // Writing 0x00 to the lower bank register selects 0x01, so banks
+        // 0x20, 0x40 and 0x60 can only be reached through 0x0000-0x3fff in
+        // mode 1 on 1 MiB+ cartridges.
+        let bank = if address < 0x4000 {
+            if self.mode == 1 { (self.bank_2 as usize) << 5 } else { 0 }
+        } else {
+            (self.bank_2 as usize) << 5 | self.rom_bank as usize
+        };
+        read_bank(&self.rom, ROM_BANK_SIZE, bank, address as usize % ROM_BANK_SIZE)
+    }
+
+    fn write_rom(&mut self, address: u16, byte: u8) {
+        match address {
+            0x0000..=0x1fff => self.ram_enabled = byte & 0x0f == 0x0a,
+            0x2000..=0x3fff => {
+                self.rom_bank = byte & 0x1f;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            },
+            0x4000..=0x5fff => self.bank_2 = byte & 0x03,
+            _ => self.mode = byte & 0x01,
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+        read_bank(&self.ram, RAM_BANK_SIZE, self.ram_bank(), address as usize)
+    }
+
+    fn write_ram(&mut self, address: u16, byte: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        let bank = self.ram_bank();
+        write_bank(&mut self.ram, RAM_BANK_SIZE, bank, address as usize, byte);
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mbc::MemoryBankController;
+    use mbc::tests::cartridge;
+    use super::MBC1;
+
+    // 2 MiB of ROM and 32 KiB of RAM
+    fn mbc1() -> MBC1 {
+        MBC1::new(cartridge(0x03, 0x06, 0x03))
+    }
+
+    #[test]
+    fn bank_0_maps_to_1() {
+        let mut mbc = mbc1();
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mbc.read_rom(0x4000), 0x05);
+
+        // the upper bits don't take part in the check
+        for &(upper, bank) in [(1, 0x21), (2, 0x41), (3, 0x61)].iter() {
+            mbc.write_rom(0x4000, upper);
+            mbc.write_rom(0x2000, 0x00);
+            assert_eq!(mbc.read_rom(0x4000), bank);
+        }
+    }
+
+    #[test]
+    fn mode_1_banks_the_first_rom_area() {
+        let mut mbc = mbc1();
+        mbc.write_rom(0x4000, 0x02);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mbc.read_rom(0x0000), 0x40);
+        assert_eq!(mbc.read_rom(0x4000), 0x41);
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+    }
+
+    #[test]
+    fn disabled_ram_reads_0xff() {
+        let mut mbc = mbc1();
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.read_ram(0x0000), 0xff);
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0xff);
+    }
+}
@@ -0,0 +1,112 @@
+use cartridge::Cartridge;
+use mbc::{MemoryBankController, ROM_BANK_SIZE, read_bank};
+
+// 512 half-byte cells built into the controller
+const RAM_SIZE: usize = 0x200;
+
+pub struct MBC2 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    // 4 bit register, 0 is treated as 1
+    rom_bank: u8,
+}
+
+impl MBC2 {
+    pub fn new(cartridge: Cartridge) -> MBC2 {
+        MBC2 {
+            rom: cartridge.rom,
+            ram: vec![0u8; RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl MemoryBankController for MBC2 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = if address < 0x4000 { 0 } else { self.rom_bank as usize };
+        read_bank(&self.rom, ROM_BANK_SIZE, bank, address as usize % ROM_BANK_SIZE)
+    }
+
+    fn write_rom(&mut self, address: u16, byte: u8) {
+        if address >= 0x4000 {
+            return;
+        }
+        // both registers share 0x0000-0x3fff, address bit 8 picks one
+        if address & 0x0100 == 0 {
+            self.ram_enabled = byte & 0x0f == 0x0a;
+        } else {
+            self.rom_bank = byte & 0x0f;
+            if self.rom_bank == 0 {
+                self.rom_bank = 1;
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+        // only the lower nibble is backed, the upper one reads as set
+        self.ram[address as usize % RAM_SIZE] | 0xf0
+    }
+
+    fn write_ram(&mut self, address: u16, byte: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        self.ram[address as usize % RAM_SIZE] = byte & 0x0f;
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mbc::MemoryBankController;
+    use mbc::tests::cartridge;
+    use super::MBC2;
+
+    // 256 KiB, 16 banks
+    fn mbc2() -> MBC2 {
+        MBC2::new(cartridge(0x06, 0x03, 0x00))
+    }
+
+    #[test]
+    fn address_bit_8_selects_the_register() {
+        let mut mbc = mbc2();
+        mbc.write_rom(0x2100, 0x03);
+        assert_eq!(mbc.read_rom(0x4000), 0x03);
+        // A8 clear enables RAM instead, whatever the address
+        mbc.write_rom(0x2000, 0x0a);
+        assert_eq!(mbc.read_rom(0x4000), 0x03);
+        mbc.write_ram(0x0000, 0x05);
+        assert_eq!(mbc.read_ram(0x0000), 0xf5);
+        mbc.write_rom(0x0100, 0x04);
+        assert_eq!(mbc.read_rom(0x4000), 0x04);
+        assert_eq!(mbc.read_ram(0x0000), 0xf5);
+        mbc.write_rom(0x0100, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+        mbc.write_rom(0x3e00, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0xff);
+    }
+
+    #[test]
+    fn ram_only_stores_the_lower_nibble() {
+        let mut mbc = mbc2();
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_ram(0x0010, 0xa5);
+        assert_eq!(mbc.read_ram(0x0010), 0xf5);
+        assert_eq!(mbc.ram()[0x10], 0x05);
+        // 512 cells mirrored across the whole area
+        assert_eq!(mbc.read_ram(0x0210), 0xf5);
+        assert_eq!(mbc.read_ram(0x1e10), 0xf5);
+    }
+}
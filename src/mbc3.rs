@@ -0,0 +1,141 @@
+use cartridge::Cartridge;
+use mbc::{MemoryBankController, ROM_BANK_SIZE, RAM_BANK_SIZE, read_bank, write_bank};
+use rtc::{Clock, Rtc, SystemClock, SECONDS, DAY_HIGH};
+
+pub struct MBC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+    ram_enabled: bool,
+    // 7 bit register, 0 is treated as 1
+    rom_bank: u8,
+    // 0x00-0x03 selects a RAM bank, 0x08-0x0c an RTC register
+    ram_bank: u8,
+}
+
+impl MBC3 {
+    pub fn new(cartridge: Cartridge) -> MBC3 {
+        MBC3::with_clock(cartridge, Box::new(SystemClock))
+    }
+
+    pub fn with_clock(cartridge: Cartridge, clock: Box<dyn Clock>) -> MBC3 {
+        let rtc = match cartridge.cartridge_type {
+            0x0f | 0x10 => Some(Rtc::new(clock)),
+            _ => None,
+        };
+        MBC3 {
+            ram: vec![0u8; cartridge.ram_size],
+            rom: cartridge.rom,
+            rtc,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+}
+
+impl MemoryBankController for MBC3 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = if address < 0x4000 { 0 } else { self.rom_bank as usize };
+        read_bank(&self.rom, ROM_BANK_SIZE, bank, address as usize % ROM_BANK_SIZE)
+    }
+
+    fn write_rom(&mut self, address: u16, byte: u8) {
+        match address {
+            0x0000..=0x1fff => self.ram_enabled = byte & 0x0f == 0x0a,
+            0x2000..=0x3fff => {
+                self.rom_bank = byte & 0x7f;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            },
+            0x4000..=0x5fff => self.ram_bank = byte & 0x0f,
+            _ => {
+                if let Some(ref mut rtc) = self.rtc {
+                    rtc.write_latch(byte);
+                }
+            },
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+        match self.ram_bank {
+            0x00..=0x03 => read_bank(&self.ram, RAM_BANK_SIZE, self.ram_bank as usize, address as usize),
+            SECONDS..=DAY_HIGH => self.rtc.as_ref().map(|rtc| rtc.read(self.ram_bank)).unwrap_or(0xff),
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, byte: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        match self.ram_bank {
+            0x00..=0x03 => write_bank(&mut self.ram, RAM_BANK_SIZE, self.ram_bank as usize, address as usize, byte),
+            SECONDS..=DAY_HIGH => {
+                if let Some(ref mut rtc) = self.rtc {
+                    rtc.write(self.ram_bank, byte);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mbc::MemoryBankController;
+    use mbc::tests::cartridge;
+    use super::MBC3;
+
+    // 2 MiB of ROM, 32 KiB of RAM and a clock
+    fn mbc3() -> MBC3 {
+        MBC3::new(cartridge(0x10, 0x06, 0x03))
+    }
+
+    #[test]
+    fn bank_0_maps_to_1() {
+        let mut mbc = mbc3();
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mbc.read_rom(0x4000), 0x01);
+        // all 7 bits select the bank directly
+        mbc.write_rom(0x2000, 0x20);
+        assert_eq!(mbc.read_rom(0x4000), 0x20);
+        mbc.write_rom(0x2000, 0x7f);
+        assert_eq!(mbc.read_rom(0x4000), 0x7f);
+    }
+
+    #[test]
+    fn disabled_ram_and_clock_read_0xff() {
+        let mut mbc = mbc3();
+        mbc.write_rom(0x4000, 0x01);
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.read_ram(0x0000), 0xff);
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+
+        // the clock registers sit behind the same enable
+        mbc.write_rom(0x4000, 0x0c);
+        assert_ne!(mbc.read_ram(0x0000), 0xff);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0xff);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mbc.read_ram(0x0000), 0xff);
+    }
+}
@@ -0,0 +1,122 @@
+use cartridge::Cartridge;
+use mbc::{MemoryBankController, ROM_BANK_SIZE, RAM_BANK_SIZE, read_bank, write_bank};
+
+pub struct MBC5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    // 9 bit register, unlike the older controllers bank 0 can be mapped
+    rom_bank: u16,
+    ram_bank: u8,
+    // on rumble cartridges bit 3 of the RAM bank register drives the motor
+    rumble: bool,
+    rumble_active: bool,
+}
+
+impl MBC5 {
+    pub fn new(cartridge: Cartridge) -> MBC5 {
+        MBC5 {
+            rumble: matches!(cartridge.cartridge_type, 0x1c..=0x1e),
+            rumble_active: false,
+            ram: vec![0u8; cartridge.ram_size],
+            rom: cartridge.rom,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+}
+
+impl MemoryBankController for MBC5 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let bank = if address < 0x4000 { 0 } else { self.rom_bank as usize };
+        read_bank(&self.rom, ROM_BANK_SIZE, bank, address as usize % ROM_BANK_SIZE)
+    }
+
+    fn write_rom(&mut self, address: u16, byte: u8) {
+        match address {
+            0x0000..=0x1fff => self.ram_enabled = byte == 0x0a,
+            0x2000..=0x2fff => self.rom_bank = (self.rom_bank & 0x100) | byte as u16,
+            0x3000..=0x3fff => self.rom_bank = (self.rom_bank & 0xff) | ((byte as u16 & 0x01) << 8),
+            0x4000..=0x5fff => {
+                if self.rumble {
+                    self.ram_bank = byte & 0x07;
+                    self.rumble_active = byte & 0x08 != 0;
+                } else {
+                    self.ram_bank = byte & 0x0f;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+        read_bank(&self.ram, RAM_BANK_SIZE, self.ram_bank as usize, address as usize)
+    }
+
+    fn write_ram(&mut self, address: u16, byte: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        let bank = self.ram_bank as usize;
+        write_bank(&mut self.ram, RAM_BANK_SIZE, bank, address as usize, byte);
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rumble_active(&self) -> bool {
+        self.rumble_active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mbc::MemoryBankController;
+    use mbc::tests::cartridge;
+    use super::MBC5;
+
+    #[test]
+    fn rom_bank_is_9_bits_wide() {
+        // 8 MiB, 512 banks
+        let mut mbc = MBC5::new(cartridge(0x19, 0x08, 0x00));
+        mbc.write_rom(0x2000, 0x05);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!((mbc.read_rom(0x4000), mbc.read_rom(0x4001)), (0x05, 0x01));
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!((mbc.read_rom(0x4000), mbc.read_rom(0x4001)), (0x00, 0x01));
+        // bank 0 is not remapped
+        mbc.write_rom(0x3000, 0x00);
+        assert_eq!((mbc.read_rom(0x4000), mbc.read_rom(0x4001)), (0x00, 0x00));
+    }
+
+    #[test]
+    fn rumble_motor_follows_bit_3() {
+        let mut mbc = MBC5::new(cartridge(0x1e, 0x00, 0x03));
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_rom(0x4000, 0x00);
+        mbc.write_ram(0x0000, 0x11);
+        mbc.write_rom(0x4000, 0x08);
+        assert!(mbc.rumble_active());
+        // the motor bit doesn't select a RAM bank
+        assert_eq!(mbc.read_ram(0x0000), 0x11);
+        mbc.write_rom(0x4000, 0x00);
+        assert!(!mbc.rumble_active());
+
+        // without a motor bit 3 is part of the bank number
+        let mut mbc = MBC5::new(cartridge(0x1b, 0x00, 0x04));
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_ram(0x0000, 0x11);
+        mbc.write_rom(0x4000, 0x08);
+        assert!(!mbc.rumble_active());
+        assert_eq!(mbc.read_ram(0x0000), 0x00);
+    }
+}
@@ -1,3 +1,15 @@
+use std::fmt;
+use std::path::PathBuf;
+use mbc::MemoryBankController;
+use save::SaveFile;
+use interrupts::{Interrupt, INTERRUPT_FLAG};
+use timer::{Timer, TIMER};
+use ppu::{Ppu, LCD};
+use joypad::{Joypad, Button, JOYPAD};
+use apu::{Apu, AUDIO, NR52};
+use serial::{Serial, SerialEndpoint, SERIAL};
+use watchpoint::{Access, Watchpoint, WatchHit};
+
 pub const ROM_BANK_0:           (u16, u16) = (0x0000, 0x3fff);
 pub const ROM_BIOS:             (u16, u16) = (0x0000, 0x00ff);
 pub const ROM_HEADER:           (u16, u16) = (0x0100, 0x014f);
@@ -10,31 +22,367 @@ pub const WORKING_RAM:          (u16, u16) = (0xc000, 0xdfff);
 pub const WORKING_RAM_SHADOW:   (u16, u16) = (0xe000, 0xfdff);
 
 pub const GRAPHICS:             (u16, u16) = (0xfe00, 0xfe9f);
+pub const UNUSABLE:             (u16, u16) = (0xfea0, 0xfeff);
 pub const IO:                   (u16, u16) = (0xff00, 0xff7f);
-pub const ZERO_PAGE:            (u16, u16) = (0xff80, 0xffff);
+pub const ZERO_PAGE:            (u16, u16) = (0xff80, 0xfffe);
+pub const INTERRUPT_ENABLE:     (u16, u16) = (0xffff, 0xffff);
+
+// Named regions, for tools that let the user pick one
+pub const REGIONS: [(&str, (u16, u16)); 13] = [
+    ("ROM_BANK_0", ROM_BANK_0),
+    ("ROM_BIOS", ROM_BIOS),
+    ("ROM_HEADER", ROM_HEADER),
+    ("ROM_BANK_OTHER", ROM_BANK_OTHER),
+    ("VRAM", VRAM),
+    ("EXT_RAM", EXT_RAM),
+    ("WORKING_RAM", WORKING_RAM),
+    ("WORKING_RAM_SHADOW", WORKING_RAM_SHADOW),
+    ("GRAPHICS", GRAPHICS),
+    ("UNUSABLE", UNUSABLE),
+    ("IO", IO),
+    ("ZERO_PAGE", ZERO_PAGE),
+    ("INTERRUPT_ENABLE", INTERRUPT_ENABLE),
+];
+
+pub const BOOT_ROM_DISABLE:     u16 = 0xff50;
+pub const DMA:                  u16 = 0xff46;
+
+// OAM DMA copies one byte per M-cycle after a one M-cycle startup delay
+const DMA_LENGTH:               u16 = 0xa0;
+
+// IO register values the DMG boot ROM leaves behind when it hands over to the
+// cartridge.
+const POST_BOOT_IO: [(u16, u8); 36] = [
+    (0xff00, 0xcf), (0xff01, 0x00), (0xff02, 0x7e), (0xff05, 0x00),
+    (0xff06, 0x00), (0xff07, 0xf8), (0xff0f, 0xe1), (0xff10, 0x80),
+    (0xff11, 0xbf), (0xff12, 0xf3), (0xff13, 0xff), (0xff14, 0xbf),
+    (0xff16, 0x3f), (0xff17, 0x00), (0xff18, 0xff), (0xff19, 0xbf),
+    (0xff1a, 0x7f), (0xff1b, 0xff), (0xff1c, 0x9f), (0xff1d, 0xff),
+    (0xff1e, 0xbf), (0xff20, 0xff), (0xff21, 0x00), (0xff22, 0x00),
+    (0xff23, 0xbf), (0xff24, 0x77), (0xff25, 0xf3), (0xff26, 0xf1),
+    (0xff40, 0x91), (0xff41, 0x85), (0xff42, 0x00), (0xff43, 0x00),
+    (0xff45, 0x00), (0xff47, 0xfc), (0xff4a, 0x00), (0xff4b, 0x00),
+];
 
+fn within(address: u16, region: (u16, u16)) -> bool {
+    address >= region.0 && address <= region.1
+}
+
+fn size(region: (u16, u16)) -> usize {
+    (region.1 - region.0) as usize + 1
+}
+
+// The memory bus. Every CPU access, opcode fetches included, is routed to the
+// component backing the addressed region.
 pub struct Memory {
-    pub buffer: [u8; 0xffff],
+    mbc: Box<dyn MemoryBankController>,
+    save_file: Option<SaveFile>,
+    timer: Timer,
+    ppu: Ppu,
+    joypad: Joypad,
+    apu: Apu,
+    serial: Serial,
+    boot_rom: Option<Vec<u8>>,
+    working_ram: Vec<u8>,
+    io: Vec<u8>,
+    zero_page: Vec<u8>,
+    interrupt_flag: u8,
+    interrupt_enable: u8,
+    dma_source: u8,
+    // M-cycles since the running OAM DMA transfer was started
+    dma_position: Option<u16>,
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Option<WatchHit>,
+}
+
+// A boot ROM that doesn't fill ROM_BIOS exactly
+#[derive(Debug)]
+pub struct BootRomError {
+    pub length: usize,
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "boot ROM is {} bytes long, expected {}", self.length, size(ROM_BIOS))
+    }
 }
 
 impl Memory {
-    pub fn new() -> Memory {
-        Memory {
-            buffer: [0u8; 0xffff]
+    pub fn new(mbc: Box<dyn MemoryBankController>, boot_rom: Option<Vec<u8>>) -> Result<Memory, BootRomError> {
+        if let Some(ref boot_rom) = boot_rom {
+            if boot_rom.len() != size(ROM_BIOS) {
+                return Err(BootRomError { length: boot_rom.len() });
+            }
+        }
+        Ok(Memory {
+            mbc,
+            save_file: None,
+            timer: Timer::new(),
+            ppu: Ppu::new(),
+            joypad: Joypad::new(),
+            apu: Apu::new(),
+            serial: Serial::new(),
+            boot_rom,
+            working_ram: vec![0u8; size(WORKING_RAM)],
+            io: vec![0u8; size(IO)],
+            zero_page: vec![0u8; size(ZERO_PAGE)],
+            interrupt_flag: 0,
+            interrupt_enable: 0,
+            dma_source: 0,
+            dma_position: None,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+        })
+    }
+
+    // Puts the IO registers into the state the boot ROM would have left them in.
+    pub fn skip_boot(&mut self) {
+        // the boot ROM powers up the APU before touching its registers
+        self.write(NR52, 0x80);
+        for &(address, byte) in POST_BOOT_IO.iter() {
+            self.write(address, byte);
         }
+        self.write(BOOT_ROM_DISABLE, 0x01);
+        self.timer.skip_boot();
     }
 
-    pub fn clear_vram(&mut self) {
-        for i in VRAM.0..=VRAM.1 {
-            self.buffer[i as usize] = 0;
+    // Advances the hardware clocked alongside the CPU.
+    pub fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles / 4 {
+            self.step_dma();
+        }
+        if self.timer.tick(cycles) {
+            self.request_interrupt(Interrupt::Timer);
+        }
+        self.interrupt_flag |= self.ppu.tick(cycles);
+        self.apu.tick(cycles);
+        if self.serial.tick(cycles) {
+            self.request_interrupt(Interrupt::Serial);
         }
     }
 
-    pub fn write(&mut self, address: usize, byte: u8) {
-        self.buffer[address] = byte;
+    fn step_dma(&mut self) {
+        if let Some(position) = self.dma_position {
+            if position > 0 {
+                let index = position - 1;
+                let byte = self.read_internal((self.dma_source as u16) << 8 | index);
+                self.ppu.write_oam(index, byte);
+            }
+            self.dma_position = if position == DMA_LENGTH { None } else { Some(position + 1) };
+        }
     }
 
-    pub fn read(&mut self, address: usize) -> u8 {
-        self.buffer[address]
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.joypad.set_button(button, pressed) {
+            self.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    pub fn connect_serial(&mut self, endpoint: Box<dyn SerialEndpoint>) {
+        self.serial.connect(endpoint);
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    // Backs the cartridge RAM with a .sav file, loading it if it exists.
+    pub fn attach_save(&mut self, path: PathBuf) {
+        let mut save_file = SaveFile::new(path);
+        save_file.load(&mut *self.mbc);
+        self.save_file = Some(save_file);
+    }
+
+    pub fn flush_save(&mut self) {
+        if let Some(ref mut save_file) = self.save_file {
+            save_file.flush(&mut *self.mbc);
+        }
+    }
+
+    pub fn rumble_active(&self) -> bool {
+        self.mbc.rumble_active()
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag |= interrupt.mask();
+    }
+
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag &= !interrupt.mask();
+    }
+
+    // Interrupts that are both requested and enabled
+    pub fn pending_interrupts(&self) -> u8 {
+        self.interrupt_flag & self.interrupt_enable & 0x1f
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.watchpoints.len() {
+            Some(self.watchpoints.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    // Only the first hit is kept until someone takes it
+    fn watch(&mut self, address: u16, access: Access, value: u8) {
+        if self.watch_hit.is_some() {
+            return;
+        }
+        if let Some(&watchpoint) = self.watchpoints.iter().find(|w| w.matches(address, access, value)) {
+            self.watch_hit = Some(WatchHit { address, access, value, watchpoint });
+        }
+    }
+
+    pub fn write(&mut self, address: u16, byte: u8) {
+        if self.dma_position.is_some() && address < IO.0 {
+            return;
+        }
+        if !self.watchpoints.is_empty() {
+            self.watch(address, Access::Write, byte);
+        }
+        self.write_internal(address, byte);
+    }
+
+    // Writes without the restrictions the CPU is subject to, for tools.
+    pub fn write_internal(&mut self, address: u16, byte: u8) {
+        if within(address, ROM_BANK_0) || within(address, ROM_BANK_OTHER) {
+            // ROM writes program the bank controller
+            self.mbc.write_rom(address, byte);
+        } else if within(address, VRAM) {
+            self.ppu.write_vram(address - VRAM.0, byte);
+        } else if within(address, EXT_RAM) {
+            self.mbc.write_ram(address - EXT_RAM.0, byte);
+        } else if within(address, WORKING_RAM) {
+            self.working_ram[(address - WORKING_RAM.0) as usize] = byte;
+        } else if within(address, WORKING_RAM_SHADOW) {
+            self.working_ram[(address - WORKING_RAM_SHADOW.0) as usize] = byte;
+        } else if within(address, GRAPHICS) {
+            self.ppu.write_oam(address - GRAPHICS.0, byte);
+        } else if within(address, UNUSABLE) {
+            // writes to the unusable area are ignored
+        } else if address == BOOT_ROM_DISABLE {
+            // once unmapped the boot ROM stays gone until the next reset
+            if byte != 0 {
+                self.boot_rom = None;
+            }
+            self.io[(address - IO.0) as usize] = byte;
+        } else if address == JOYPAD {
+            if self.joypad.write(byte) {
+                self.request_interrupt(Interrupt::Joypad);
+            }
+        } else if within(address, SERIAL) {
+            self.serial.write(address, byte);
+        } else if within(address, TIMER) {
+            self.timer.write(address, byte);
+        } else if within(address, AUDIO) {
+            self.apu.write(address, byte);
+        } else if address == DMA {
+            // restarting a running transfer starts over from the new source
+            self.dma_source = byte;
+            self.dma_position = Some(0);
+        } else if within(address, LCD) {
+            self.ppu.write(address, byte);
+        } else if address == INTERRUPT_FLAG {
+            self.interrupt_flag = byte & 0x1f;
+        } else if within(address, IO) {
+            self.io[(address - IO.0) as usize] = byte;
+        } else if within(address, ZERO_PAGE) {
+            self.zero_page[(address - ZERO_PAGE.0) as usize] = byte;
+        } else {
+            self.interrupt_enable = byte;
+        }
+    }
+
+    pub fn read(&mut self, address: u16) -> u8 {
+        self.access(address, Access::Read)
+    }
+
+    // Opcode fetches, which trip execute rather than read watchpoints
+    pub fn fetch(&mut self, address: u16) -> u8 {
+        self.access(address, Access::Execute)
+    }
+
+    fn access(&mut self, address: u16, access: Access) -> u8 {
+        // while OAM DMA owns the bus the CPU only sees the IO registers and HRAM
+        if self.dma_position.is_some() && address < IO.0 {
+            return 0xff;
+        }
+        let byte = self.read_internal(address);
+        if !self.watchpoints.is_empty() {
+            self.watch(address, access, byte);
+        }
+        byte
+    }
+
+    // Reads without the restrictions the CPU is subject to, for DMA and tools.
+    pub fn read_internal(&self, address: u16) -> u8 {
+        if let Some(ref boot_rom) = self.boot_rom {
+            if within(address, ROM_BIOS) {
+                return boot_rom[address as usize];
+            }
+        }
+        if within(address, ROM_BANK_0) || within(address, ROM_BANK_OTHER) {
+            self.mbc.read_rom(address)
+        } else if within(address, VRAM) {
+            self.ppu.read_vram(address - VRAM.0)
+        } else if within(address, EXT_RAM) {
+            self.mbc.read_ram(address - EXT_RAM.0)
+        } else if within(address, WORKING_RAM) {
+            self.working_ram[(address - WORKING_RAM.0) as usize]
+        } else if within(address, WORKING_RAM_SHADOW) {
+            self.working_ram[(address - WORKING_RAM_SHADOW.0) as usize]
+        } else if within(address, GRAPHICS) {
+            self.ppu.read_oam(address - GRAPHICS.0)
+        } else if within(address, UNUSABLE) {
+            0x00
+        } else if address == JOYPAD {
+            self.joypad.read()
+        } else if within(address, SERIAL) {
+            self.serial.read(address)
+        } else if within(address, TIMER) {
+            self.timer.read(address)
+        } else if within(address, AUDIO) {
+            self.apu.read(address)
+        } else if address == DMA {
+            self.dma_source
+        } else if within(address, LCD) {
+            self.ppu.read(address)
+        } else if address == INTERRUPT_FLAG {
+            // the upper three bits are unused and read as set
+            self.interrupt_flag | 0xe0
+        } else if within(address, IO) {
+            self.io[(address - IO.0) as usize]
+        } else if within(address, ZERO_PAGE) {
+            self.zero_page[(address - ZERO_PAGE.0) as usize]
+        } else {
+            self.interrupt_enable
+        }
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        self.flush_save();
     }
 }
@@ -0,0 +1,324 @@
+use interrupts::Interrupt;
+
+pub const SCREEN_WIDTH:     usize = 160;
+pub const SCREEN_HEIGHT:    usize = 144;
+
+pub const FRAME_CYCLES: u32 = DOTS_PER_LINE * LINES as u32;
+
+pub const LCD:      (u16, u16) = (0xff40, 0xff4b);
+
+pub const LCDC:     u16 = 0xff40;
+pub const STAT:     u16 = 0xff41;
+pub const SCY:      u16 = 0xff42;
+pub const SCX:      u16 = 0xff43;
+pub const LY:       u16 = 0xff44;
+pub const LYC:      u16 = 0xff45;
+pub const BGP:      u16 = 0xff47;
+pub const OBP0:     u16 = 0xff48;
+pub const OBP1:     u16 = 0xff49;
+pub const WY:       u16 = 0xff4a;
+pub const WX:       u16 = 0xff4b;
+
+const DOTS_PER_LINE:    u32 = 456;
+const OAM_SCAN_DOTS:    u32 = 80;
+const TRANSFER_DOTS:    u32 = 172;
+const LINES:            u8 = 154;
+const SPRITES_PER_LINE: usize = 10;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    HBlank = 0x00,
+    VBlank = 0x01,
+    OamScan = 0x02,
+    Transfer = 0x03,
+}
+
+pub struct Ppu {
+    vram: Vec<u8>,
+    oam: Vec<u8>,
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: Mode,
+    // position within the current line
+    dot: u32,
+    // the window keeps its own line counter, it only advances on lines the
+    // window was actually drawn on
+    window_line: u8,
+    // STAT interrupts fire on the rising edge of the ORed sources
+    stat_line: bool,
+    // shades 0 (white) to 3 (black), one byte per pixel
+    framebuffer: Vec<u8>,
+    frame_ready: bool,
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu {
+            vram: vec![0u8; 0x2000],
+            oam: vec![0u8; 0xa0],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: Mode::HBlank,
+            dot: 0,
+            window_line: 0,
+            stat_line: false,
+            framebuffer: vec![0u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false,
+        }
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    // Returns whether a frame was completed since the last call.
+    pub fn frame_ready(&mut self) -> bool {
+        let ready = self.frame_ready;
+        self.frame_ready = false;
+        ready
+    }
+
+    pub fn read_vram(&self, address: u16) -> u8 {
+        self.vram[address as usize]
+    }
+
+    pub fn write_vram(&mut self, address: u16, byte: u8) {
+        self.vram[address as usize] = byte;
+    }
+
+    pub fn read_oam(&self, address: u16) -> u8 {
+        self.oam[address as usize]
+    }
+
+    pub fn write_oam(&mut self, address: u16, byte: u8) {
+        self.oam[address as usize] = byte;
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            LCDC => self.lcdc,
+            STAT => {
+                let coincidence = if self.ly == self.lyc { 0x04 } else { 0x00 };
+                0x80 | (self.stat & 0x78) | coincidence | self.mode as u8
+            },
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly,
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            WX => self.wx,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, byte: u8) {
+        match address {
+            LCDC => {
+                let enabled = self.enabled();
+                self.lcdc = byte;
+                if enabled && !self.enabled() {
+                    // switching the LCD off resets it to the top of the screen
+                    self.ly = 0;
+                    self.dot = 0;
+                    self.window_line = 0;
+                    self.mode = Mode::HBlank;
+                } else if !enabled && self.enabled() {
+                    self.mode = Mode::OamScan;
+                }
+            },
+            STAT => self.stat = byte & 0x78,
+            SCY => self.scy = byte,
+            SCX => self.scx = byte,
+            LYC => self.lyc = byte,
+            BGP => self.bgp = byte,
+            OBP0 => self.obp0 = byte,
+            OBP1 => self.obp1 = byte,
+            WY => self.wy = byte,
+            WX => self.wx = byte,
+            // LY is read-only
+            _ => {},
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.lcdc & 0x80 != 0
+    }
+
+    // Advances the PPU and returns the interrupts it requested as IF bits.
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        let mut interrupts = 0;
+        if !self.enabled() {
+            return interrupts;
+        }
+        for _ in 0..cycles {
+            self.dot += 1;
+            if self.ly < SCREEN_HEIGHT as u8 {
+                if self.dot == OAM_SCAN_DOTS {
+                    self.mode = Mode::Transfer;
+                } else if self.dot == OAM_SCAN_DOTS + TRANSFER_DOTS {
+                    self.render_line();
+                    self.mode = Mode::HBlank;
+                }
+            }
+            if self.dot == DOTS_PER_LINE {
+                self.dot = 0;
+                self.ly = (self.ly + 1) % LINES;
+                if self.ly == SCREEN_HEIGHT as u8 {
+                    self.mode = Mode::VBlank;
+                    self.frame_ready = true;
+                    interrupts |= Interrupt::VBlank.mask();
+                } else if self.ly == 0 {
+                    self.window_line = 0;
+                    self.mode = Mode::OamScan;
+                } else if self.ly < SCREEN_HEIGHT as u8 {
+                    self.mode = Mode::OamScan;
+                }
+            }
+            if self.update_stat_line() {
+                interrupts |= Interrupt::LcdStat.mask();
+            }
+        }
+        interrupts
+    }
+
+    // Returns true on a rising edge of the STAT interrupt line.
+    fn update_stat_line(&mut self) -> bool {
+        let line = (self.stat & 0x40 != 0 && self.ly == self.lyc)
+            || (self.stat & 0x20 != 0 && self.mode == Mode::OamScan)
+            || (self.stat & 0x10 != 0 && self.mode == Mode::VBlank)
+            || (self.stat & 0x08 != 0 && self.mode == Mode::HBlank);
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        rising
+    }
+
+    // Color index (0-3) of a pixel within a tile, `tile` being the VRAM
+    // offset of the tile's first byte.
+    fn tile_pixel(&self, tile: usize, x: u8, y: u8) -> u8 {
+        let lo = self.vram[tile + y as usize * 2];
+        let hi = self.vram[tile + y as usize * 2 + 1];
+        let bit = 7 - x;
+        ((hi >> bit) & 0x01) << 1 | ((lo >> bit) & 0x01)
+    }
+
+    // Background and window tiles are addressed either unsigned from 0x8000
+    // or signed from 0x9000, depending on LCDC bit 4.
+    fn background_tile(&self, index: u8) -> usize {
+        if self.lcdc & 0x10 != 0 {
+            index as usize * 16
+        } else {
+            (0x1000 + (index as i8 as isize) * 16) as usize
+        }
+    }
+
+    fn map_pixel(&self, map: usize, x: u8, y: u8) -> u8 {
+        let index = self.vram[map + (y as usize / 8) * 32 + x as usize / 8];
+        self.tile_pixel(self.background_tile(index), x % 8, y % 8)
+    }
+
+    fn render_line(&mut self) {
+        let ly = self.ly;
+        let row = ly as usize * SCREEN_WIDTH;
+        // color indices before palette lookup, sprites need them for priority
+        let mut background = [0u8; SCREEN_WIDTH];
+
+        // on the DMG LCDC bit 0 blanks both background and window
+        if self.lcdc & 0x01 != 0 {
+            let map = if self.lcdc & 0x08 != 0 { 0x1c00 } else { 0x1800 };
+            let y = ly.wrapping_add(self.scy);
+            for (x, pixel) in background.iter_mut().enumerate() {
+                *pixel = self.map_pixel(map, (x as u8).wrapping_add(self.scx), y);
+            }
+
+            let window_x = self.wx as isize - 7;
+            if self.lcdc & 0x20 != 0 && ly >= self.wy && window_x < SCREEN_WIDTH as isize {
+                let map = if self.lcdc & 0x40 != 0 { 0x1c00 } else { 0x1800 };
+                let start = window_x.max(0) as usize;
+                for (x, pixel) in background.iter_mut().enumerate().skip(start) {
+                    *pixel = self.map_pixel(map, (x as isize - window_x) as u8, self.window_line);
+                }
+                self.window_line += 1;
+            }
+        }
+
+        for (x, &pixel) in background.iter().enumerate() {
+            self.framebuffer[row + x] = (self.bgp >> (pixel * 2)) & 0x03;
+        }
+
+        if self.lcdc & 0x02 != 0 {
+            self.render_sprites(&background);
+        }
+    }
+
+    fn render_sprites(&mut self, background: &[u8; SCREEN_WIDTH]) {
+        let ly = self.ly as isize;
+        let row = self.ly as usize * SCREEN_WIDTH;
+        let height = if self.lcdc & 0x04 != 0 { 16 } else { 8 };
+
+        // OAM scan: the first ten sprites overlapping this line, in OAM order
+        let mut sprites: Vec<usize> = (0..40)
+            .filter(|&i| {
+                let y = self.oam[i * 4] as isize - 16;
+                ly >= y && ly < y + height
+            })
+            .take(SPRITES_PER_LINE)
+            .collect();
+        // on the DMG the sprite further left wins, ties go to the lower index
+        sprites.sort_by_key(|&i| (self.oam[i * 4 + 1], i));
+
+        for x in 0..SCREEN_WIDTH as isize {
+            for &i in sprites.iter() {
+                let sprite_x = self.oam[i * 4 + 1] as isize - 8;
+                if x < sprite_x || x >= sprite_x + 8 {
+                    continue;
+                }
+                let attributes = self.oam[i * 4 + 3];
+                let mut tile_y = ly - (self.oam[i * 4] as isize - 16);
+                if attributes & 0x40 != 0 {
+                    tile_y = height - 1 - tile_y;
+                }
+                let mut tile_x = x - sprite_x;
+                if attributes & 0x20 != 0 {
+                    tile_x = 7 - tile_x;
+                }
+                let mut index = self.oam[i * 4 + 2];
+                if height == 16 {
+                    index &= 0xfe;
+                }
+                let tile = index as usize * 16 + (tile_y as usize / 8) * 16;
+                let color = self.tile_pixel(tile, tile_x as u8, tile_y as u8 % 8);
+                if color == 0 {
+                    // transparent, a lower priority sprite may show through
+                    continue;
+                }
+                // with bit 7 set the sprite hides behind background colors 1-3
+                if attributes & 0x80 == 0 || background[x as usize] == 0 {
+                    let palette = if attributes & 0x10 != 0 { self.obp1 } else { self.obp0 };
+                    self.framebuffer[row + x as usize] = (palette >> (color * 2)) & 0x03;
+                }
+                break;
+            }
+        }
+    }
+}
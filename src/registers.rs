@@ -1,5 +1,7 @@
 use flags::Flags;
 
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum Register {
     A,
     B,
@@ -13,6 +15,7 @@ pub enum Register {
     BC,
     DE,
     HL,
+    SP,
 }
 
 pub struct Registers {
@@ -24,26 +27,36 @@ pub struct Registers {
     pub pc: u16,
 }
 
+#[repr(C)]
+#[derive(Clone, Copy)]
 pub struct AFPair {
-    pub a: u8,
     pub f: u8,
+    pub a: u8,
 }
 
+#[repr(C)]
+#[derive(Clone, Copy)]
 pub struct BCPair {
-    pub b: u8,
     pub c: u8,
+    pub b: u8,
 }
 
+#[repr(C)]
+#[derive(Clone, Copy)]
 pub struct DEPair {
-    pub d: u8,
     pub e: u8,
+    pub d: u8,
 }
 
+#[repr(C)]
+#[derive(Clone, Copy)]
 pub struct HLPair {
-    pub h: u8,
     pub l: u8,
+    pub h: u8,
 }
 
+// The pairs are laid out low byte first, so the 16-bit views are only
+// correct on little-endian hosts.
 pub union AF {
     pub pair: AFPair,
     pub af: u16,
@@ -85,7 +98,7 @@ impl Registers {
         }
     }
 
-    pub fn get_af(&mut self) -> u16 {
+    pub fn get_af(&self) -> u16 {
         unsafe {
             self.af.af
         }
@@ -103,7 +116,7 @@ impl Registers {
         }
     }
 
-    pub fn get_bc(&mut self) -> u16 {
+    pub fn get_bc(&self) -> u16 {
         unsafe {
             self.bc.bc
         }
@@ -121,7 +134,7 @@ impl Registers {
         }
     }
 
-    pub fn get_de(&mut self) -> u16 {
+    pub fn get_de(&self) -> u16 {
         unsafe {
             self.de.de
         }
@@ -139,7 +152,7 @@ impl Registers {
         }
     }
 
-    pub fn get_hl(&mut self) -> u16 {
+    pub fn get_hl(&self) -> u16 {
         unsafe {
             self.hl.hl
         }
@@ -158,19 +171,16 @@ impl Registers {
     }
 
     pub fn set_af(&mut self, data: u16) {
-        self.af.af = data;
+        // the lower nibble of F is hardwired to zero
+        self.af.af = data & 0xfff0;
     }
 
     pub fn set_a(&mut self, byte: u8) {
-        unsafe {
-            self.af.pair.a = byte;
-        }
+        self.af.pair.a = byte;
     }
 
     pub fn set_f(&mut self, byte: u8) {
-        unsafe {
-            self.af.pair.f = byte;
-        }
+        self.af.pair.f = byte;
     }
 
     pub fn set_bc(&mut self, data: u16) {
@@ -178,15 +188,11 @@ impl Registers {
     }
 
     pub fn set_b(&mut self, byte: u8) {
-        unsafe {
-            self.bc.pair.b = byte;
-        }
+        self.bc.pair.b = byte;
     }
 
     pub fn set_c(&mut self, byte: u8) {
-        unsafe {
-            self.bc.pair.c = byte;
-        }
+        self.bc.pair.c = byte;
     }
 
     pub fn set_de(&mut self, data: u16) {
@@ -194,15 +200,11 @@ impl Registers {
     }
 
     pub fn set_d(&mut self, byte: u8) {
-        unsafe {
-            self.de.pair.d = byte;
-        }
+        self.de.pair.d = byte;
     }
 
     pub fn set_e(&mut self, byte: u8) {
-        unsafe {
-            self.de.pair.e = byte;
-        }
+        self.de.pair.e = byte;
     }
 
     pub fn set_hl(&mut self, data: u16) {
@@ -210,90 +212,128 @@ impl Registers {
     }
 
     pub fn set_h(&mut self, byte: u8) {
-        unsafe {
-            self.hl.pair.h = byte;
-        }
+        self.hl.pair.h = byte;
     }
 
     pub fn set_l(&mut self, byte: u8) {
-        unsafe {
-            self.hl.pair.l = byte;
+        self.hl.pair.l = byte;
+    }
+
+    pub fn get_8(&self, register: Register) -> u8 {
+        match register {
+            Register::A => self.get_a(),
+            Register::B => self.get_b(),
+            Register::C => self.get_c(),
+            Register::D => self.get_d(),
+            Register::E => self.get_e(),
+            Register::F => self.get_f(),
+            Register::H => self.get_h(),
+            Register::L => self.get_l(),
+            _ => panic!("Invalid register provided!"),
         }
     }
 
-    pub fn dec_hl(&mut self) {
-        unsafe {
-            self.hl.hl -= 1;
+    pub fn set_8(&mut self, register: Register, byte: u8) {
+        match register {
+            Register::A => self.set_a(byte),
+            Register::B => self.set_b(byte),
+            Register::C => self.set_c(byte),
+            Register::D => self.set_d(byte),
+            Register::E => self.set_e(byte),
+            Register::F => self.set_f(byte & 0xf0),
+            Register::H => self.set_h(byte),
+            Register::L => self.set_l(byte),
+            _ => panic!("Invalid register provided!"),
         }
     }
 
-    pub fn inc_c(&mut self) {
-        unsafe {
-            self.bc.pair.c += 1;
+    pub fn get_16(&self, register: Register) -> u16 {
+        match register {
+            Register::AF => self.get_af(),
+            Register::BC => self.get_bc(),
+            Register::DE => self.get_de(),
+            Register::HL => self.get_hl(),
+            Register::SP => self.sp,
+            _ => panic!("Invalid register provided!"),
+        }
+    }
+
+    pub fn set_16(&mut self, register: Register, data: u16) {
+        match register {
+            Register::AF => self.set_af(data),
+            Register::BC => self.set_bc(data),
+            Register::DE => self.set_de(data),
+            Register::HL => self.set_hl(data),
+            Register::SP => self.sp = data,
+            _ => panic!("Invalid register provided!"),
         }
     }
 
-    pub fn set_bit(&mut self, byte: u8, n: u8) -> u8 {
+    pub fn inc_hl(&mut self) {
+        let hl = self.get_hl();
+        self.set_hl(hl.wrapping_add(1));
+    }
+
+    pub fn dec_hl(&mut self) {
+        let hl = self.get_hl();
+        self.set_hl(hl.wrapping_sub(1));
+    }
+
+    pub fn set_bit(&self, byte: u8, n: u8) -> u8 {
         byte | 1 << n
     }
 
-    pub fn clear_bit(&mut self, byte: u8, n: u8) -> u8 {
+    pub fn clear_bit(&self, byte: u8, n: u8) -> u8 {
         byte & !(1 << n)
     }
 
     pub fn set_flag_z(&mut self) {
         let f = self.get_f();
-        unsafe {
-            self.af.pair.f = self.set_bit(f, Flags::Z as u8);
-        }
+        self.af.pair.f = self.set_bit(f, Flags::Z as u8);
     }
 
     pub fn clear_flag_z(&mut self) {
         let f = self.get_f();
-        unsafe {
-            self.af.pair.f = self.clear_bit(f, Flags::Z as u8);
-        }
+        self.af.pair.f = self.clear_bit(f, Flags::Z as u8);
     }
 
     pub fn set_flag_n(&mut self) {
         let f = self.get_f();
-        unsafe {
-            self.af.pair.f = self.set_bit(f, Flags::N as u8);
-        }
+        self.af.pair.f = self.set_bit(f, Flags::N as u8);
     }
 
     pub fn clear_flag_n(&mut self) {
         let f = self.get_f();
-        unsafe {
-            self.af.pair.f = self.clear_bit(f, Flags::N as u8);
-        }
+        self.af.pair.f = self.clear_bit(f, Flags::N as u8);
     }
 
     pub fn set_flag_h(&mut self) {
         let f = self.get_f();
-        unsafe {
-            self.af.pair.f = self.set_bit(f, Flags::H as u8);
-        }
+        self.af.pair.f = self.set_bit(f, Flags::H as u8);
     }
 
     pub fn clear_flag_h(&mut self) {
         let f = self.get_f();
-        unsafe {
-            self.af.pair.f = self.clear_bit(f, Flags::H as u8);
-        }
+        self.af.pair.f = self.clear_bit(f, Flags::H as u8);
     }
 
     pub fn set_flag_c(&mut self) {
         let f = self.get_f();
-        unsafe {
-            self.af.pair.f = self.set_bit(f, Flags::C as u8);
-        }
+        self.af.pair.f = self.set_bit(f, Flags::C as u8);
     }
 
     pub fn clear_flag_c(&mut self) {
         let f = self.get_f();
-        unsafe {
-            self.af.pair.f = self.clear_bit(f, Flags::C as u8);
+        self.af.pair.f = self.clear_bit(f, Flags::C as u8);
+    }
+
+    pub fn set_flag(&mut self, flag: Flags, value: bool) {
+        if value {
+            let f = self.get_f();
+            self.set_f(self.set_bit(f, flag as u8));
+        } else {
+            let f = self.get_f();
+            self.set_f(self.clear_bit(f, flag as u8));
         }
     }
 
@@ -322,18 +362,18 @@ impl Registers {
     }
 
     pub fn to_signed_byte(&self, byte: u8) -> i8 {
-        -((256 + (!byte + 1)) as i8)
+        byte as i8
     }
 
     pub fn step(&mut self, length: isize) {
-        self.pc = (self.pc as isize + length) as u16;
+        self.pc = (self.pc as isize).wrapping_add(length) as u16;
     }
 
     pub fn jump(&mut self, address: u16) {
         self.pc = address;
     }
 
-    pub fn dump(&mut self) {
+    pub fn dump(&self) {
         println!("A:  ${:02x}", self.get_a());
         println!("B:  ${:02x}", self.get_b());
         println!("C:  ${:02x}", self.get_c());
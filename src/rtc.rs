@@ -0,0 +1,294 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+// the day counter is 9 bits wide
+const DAYS: u64 = 512;
+
+// Where the RTC gets its time from, in whole seconds since an arbitrary
+// epoch. Swap it out to drive the clock deterministically.
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+}
+
+// RTC register numbers as selected through the MBC3 RAM bank register
+pub const SECONDS:      u8 = 0x08;
+pub const MINUTES:      u8 = 0x09;
+pub const HOURS:        u8 = 0x0a;
+pub const DAY_LOW:      u8 = 0x0b;
+pub const DAY_HIGH:     u8 = 0x0c;
+
+// Size of the RTC footer BGB and VBA-M append to .sav files: the live and the
+// latched registers as 32 bit words followed by a UNIX timestamp, which BGB
+// stores in 64 bits and VBA-M in 32.
+pub const FOOTER_SIZE:          usize = 48;
+pub const SHORT_FOOTER_SIZE:    usize = 44;
+// the registers, without the timestamp
+pub const FOOTER_REGISTERS:     usize = 40;
+
+pub struct Rtc {
+    clock: Box<dyn Clock>,
+    // the running counter, in seconds, as of `timestamp`
+    counter: u64,
+    timestamp: u64,
+    halted: bool,
+    carry: bool,
+    latched: [u8; 5],
+    latch: u8,
+}
+
+impl Rtc {
+    pub fn new(clock: Box<dyn Clock>) -> Rtc {
+        let timestamp = clock.now();
+        Rtc {
+            clock,
+            counter: 0,
+            timestamp,
+            halted: false,
+            carry: false,
+            latched: [0u8; 5],
+            latch: 0xff,
+        }
+    }
+
+    // Folds the time passed since the last update into the counter.
+    fn update(&mut self) {
+        let now = self.clock.now();
+        if !self.halted {
+            self.counter += now.saturating_sub(self.timestamp);
+            if self.counter >= DAYS * SECONDS_PER_DAY {
+                self.carry = true;
+                self.counter %= DAYS * SECONDS_PER_DAY;
+            }
+        }
+        self.timestamp = now;
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        let days = self.counter / SECONDS_PER_DAY;
+        let mut day_high = (days >> 8) as u8 & 0x01;
+        if self.halted {
+            day_high |= 0x40;
+        }
+        if self.carry {
+            day_high |= 0x80;
+        }
+        [
+            (self.counter % 60) as u8,
+            (self.counter / 60 % 60) as u8,
+            (self.counter / 3600 % 24) as u8,
+            days as u8,
+            day_high,
+        ]
+    }
+
+    // Writing 0x00 followed by 0x01 copies the running clock into the
+    // registers the game reads.
+    pub fn write_latch(&mut self, byte: u8) {
+        if self.latch == 0x00 && byte == 0x01 {
+            self.update();
+            self.latched = self.registers();
+        }
+        self.latch = byte;
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            SECONDS..=DAY_HIGH => self.latched[(register - SECONDS) as usize],
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, register: u8, byte: u8) {
+        self.update();
+        let mut registers = self.registers();
+        match register {
+            SECONDS => registers[0] = byte & 0x3f,
+            MINUTES => registers[1] = byte & 0x3f,
+            HOURS => registers[2] = byte & 0x1f,
+            DAY_LOW => registers[3] = byte,
+            DAY_HIGH => {
+                registers[4] = byte & 0xc1;
+                self.halted = byte & 0x40 != 0;
+                self.carry = byte & 0x80 != 0;
+            },
+            _ => return,
+        }
+        let days = (registers[4] as u64 & 0x01) << 8 | registers[3] as u64;
+        self.counter = days * SECONDS_PER_DAY
+            + registers[2] as u64 * 3600
+            + registers[1] as u64 * 60
+            + registers[0] as u64;
+    }
+
+    // The footer holds the clock as of the last update along with the time of
+    // that update, so it stays the same while the game leaves the clock alone.
+    pub fn save(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(FOOTER_SIZE);
+        for &register in self.registers().iter().chain(self.latched.iter()) {
+            data.extend_from_slice(&(register as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&self.timestamp.to_le_bytes());
+        data
+    }
+
+    // Restores the clock from a save footer. The time that passed since the
+    // footer was written is caught up on the next update.
+    pub fn load(&mut self, data: &[u8]) {
+        if data.len() < SHORT_FOOTER_SIZE {
+            return;
+        }
+        let word = |i: usize| u32::from_le_bytes([data[i * 4], data[i * 4 + 1], data[i * 4 + 2], data[i * 4 + 3]]);
+        for i in 0..5 {
+            self.latched[i] = word(5 + i) as u8;
+        }
+        let timestamp = if data.len() >= FOOTER_SIZE {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&data[FOOTER_REGISTERS..FOOTER_SIZE]);
+            u64::from_le_bytes(bytes)
+        } else {
+            word(10) as u64
+        };
+        // registers are written with the clock halted so no time is added
+        self.halted = true;
+        for (i, register) in [SECONDS, MINUTES, HOURS, DAY_LOW, DAY_HIGH].iter().enumerate() {
+            self.write(*register, word(i) as u8);
+        }
+        self.timestamp = timestamp;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use super::*;
+
+    // A clock that only moves when the test says so
+    #[derive(Clone)]
+    struct FakeClock(Rc<Cell<u64>>);
+
+    impl FakeClock {
+        fn advance(&self, seconds: u64) {
+            self.0.set(self.0.get() + seconds);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn rtc() -> (Rtc, FakeClock) {
+        let clock = FakeClock(Rc::new(Cell::new(1_000_000)));
+        (Rtc::new(Box::new(clock.clone())), clock)
+    }
+
+    fn latch(rtc: &mut Rtc) {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+    }
+
+    fn latched(rtc: &Rtc) -> [u8; 5] {
+        [rtc.read(SECONDS), rtc.read(MINUTES), rtc.read(HOURS), rtc.read(DAY_LOW), rtc.read(DAY_HIGH)]
+    }
+
+    #[test]
+    fn latches_on_0x00_then_0x01() {
+        let (mut rtc, clock) = rtc();
+        clock.advance(2 * 3600 + 3 * 60 + 4);
+        rtc.write_latch(0x01);
+        assert_eq!(latched(&rtc), [0, 0, 0, 0, 0]);
+
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [4, 3, 2, 0, 0]);
+
+        // the latched registers hold still until the next latch
+        clock.advance(1);
+        assert_eq!(rtc.read(SECONDS), 4);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(SECONDS), 4);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(SECONDS), 5);
+    }
+
+    #[test]
+    fn day_counter_overflow_sets_carry() {
+        let (mut rtc, clock) = rtc();
+        clock.advance(511 * SECONDS_PER_DAY);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc)[3..], [0xff, 0x01]);
+
+        clock.advance(SECONDS_PER_DAY + 5);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [5, 0, 0, 0x00, 0x80]);
+
+        // the carry sticks until the game clears it
+        rtc.write(DAY_HIGH, 0x00);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(DAY_HIGH), 0x00);
+    }
+
+    #[test]
+    fn halt_stops_time() {
+        let (mut rtc, clock) = rtc();
+        clock.advance(10);
+        rtc.write(DAY_HIGH, 0x40);
+        clock.advance(3600);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [10, 0, 0, 0, 0x40]);
+
+        rtc.write(DAY_HIGH, 0x00);
+        clock.advance(20);
+        latch(&mut rtc);
+        assert_eq!(latched(&rtc), [30, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let (mut rtc, clock) = rtc();
+        rtc.write(HOURS, 5);
+        rtc.write(DAY_LOW, 0x20);
+        clock.advance(42);
+        latch(&mut rtc);
+        let footer = rtc.save();
+        assert_eq!(footer.len(), FOOTER_SIZE);
+
+        // VBA-M stores the timestamp in 32 bits
+        let mut short_footer = footer[..40].to_vec();
+        short_footer.extend_from_slice(&(clock.now() as u32).to_le_bytes());
+        assert_eq!(short_footer.len(), SHORT_FOOTER_SIZE);
+
+        for data in [footer, short_footer].iter() {
+            let later = FakeClock(Rc::new(Cell::new(clock.now())));
+            let mut loaded = Rtc::new(Box::new(later.clone()));
+            loaded.load(data);
+            assert_eq!(latched(&loaded), [42, 0, 5, 0x20, 0]);
+
+            // time spent with the emulator closed is caught up
+            later.advance(60);
+            latch(&mut loaded);
+            assert_eq!(latched(&loaded), [42, 1, 5, 0x20, 0]);
+        }
+    }
+
+    #[test]
+    fn footer_holds_still_while_the_game_leaves_the_clock_alone() {
+        let (mut rtc, clock) = rtc();
+        clock.advance(10);
+        latch(&mut rtc);
+        let footer = rtc.save();
+        clock.advance(10);
+        assert_eq!(rtc.save(), footer);
+        latch(&mut rtc);
+        assert_ne!(rtc.save()[..FOOTER_REGISTERS], footer[..FOOTER_REGISTERS]);
+    }
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::path::PathBuf;
+use mbc::MemoryBankController;
+use rtc::{FOOTER_REGISTERS, SHORT_FOOTER_SIZE};
+
+// Battery-backed external RAM persisted next to the ROM. For cartridges with
+// a clock the RTC state follows the RAM contents.
+pub struct SaveFile {
+    path: PathBuf,
+    // what was last read from or written to disk
+    saved: Vec<u8>,
+}
+
+impl SaveFile {
+    pub fn new(path: PathBuf) -> SaveFile {
+        SaveFile {
+            path,
+            saved: Vec::new(),
+        }
+    }
+
+    pub fn load(&mut self, mbc: &mut dyn MemoryBankController) {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            // no save yet
+            Err(_) => return,
+        };
+        let length = mbc.ram().len().min(data.len());
+        mbc.ram_mut()[..length].copy_from_slice(&data[..length]);
+        if let Some(rtc) = mbc.rtc() {
+            if data.len() - length >= SHORT_FOOTER_SIZE {
+                rtc.load(&data[length..]);
+            }
+        }
+        self.saved = data;
+    }
+
+    // Writes the save back to disk if it changed since the last flush. The
+    // RTC timestamp is left out of the comparison, it moves on every latch
+    // even when the clock is halted.
+    pub fn flush(&mut self, mbc: &mut dyn MemoryBankController) {
+        let mut data = mbc.ram().to_vec();
+        let mut compared = data.len();
+        if let Some(rtc) = mbc.rtc() {
+            data.extend(rtc.save());
+            compared += FOOTER_REGISTERS;
+        }
+        if self.saved.len() >= compared && data[..compared] == self.saved[..compared] {
+            return;
+        }
+        match fs::write(&self.path, &data) {
+            Ok(_) => self.saved = data,
+            Err(e) => eprintln!("Can't write {}: {}", self.path.display(), e),
+        }
+    }
+}
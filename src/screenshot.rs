@@ -0,0 +1,104 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+// RGB for the four DMG shades, lightest first
+const SHADES: [[u8; 3]; 4] = [
+    [0xff, 0xff, 0xff],
+    [0xaa, 0xaa, 0xaa],
+    [0x55, 0x55, 0x55],
+    [0x00, 0x00, 0x00],
+];
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+// the largest block a stored deflate block can hold
+const STORED_BLOCK_SIZE: usize = 0xffff;
+
+// Writes the framebuffer as a PPM if the path ends in .ppm and as a PNG
+// otherwise.
+pub fn save(path: &Path, framebuffer: &[u8]) -> io::Result<()> {
+    let data = match path.extension() {
+        Some(extension) if extension == "ppm" => ppm(framebuffer),
+        _ => png(framebuffer),
+    };
+    File::create(path)?.write_all(&data)
+}
+
+fn ppm(framebuffer: &[u8]) -> Vec<u8> {
+    let mut data = format!("P6\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT).into_bytes();
+    for &shade in framebuffer.iter() {
+        data.extend_from_slice(&SHADES[shade as usize & 0x03]);
+    }
+    data
+}
+
+// An uncompressed 8-bit RGB PNG. The image data is wrapped in stored deflate
+// blocks, which keeps the encoder trivial at the cost of file size.
+fn png(framebuffer: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity((SCREEN_WIDTH * 3 + 1) * SCREEN_HEIGHT);
+    for row in framebuffer.chunks(SCREEN_WIDTH) {
+        // filter type 0, none
+        raw.push(0);
+        for &shade in row.iter() {
+            raw.extend_from_slice(&SHADES[shade as usize & 0x03]);
+        }
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(SCREEN_WIDTH as u32).to_be_bytes());
+    header.extend_from_slice(&(SCREEN_HEIGHT as u32).to_be_bytes());
+    // bit depth 8, color type 2 (RGB), deflate, no filtering, no interlace
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut data = PNG_SIGNATURE.to_vec();
+    chunk(&mut data, b"IHDR", &header);
+    chunk(&mut data, b"IDAT", &zlib(&raw));
+    chunk(&mut data, b"IEND", &[]);
+    data
+}
+
+fn chunk(data: &mut Vec<u8>, kind: &[u8; 4], contents: &[u8]) {
+    data.extend_from_slice(&(contents.len() as u32).to_be_bytes());
+    let start = data.len();
+    data.extend_from_slice(kind);
+    data.extend_from_slice(contents);
+    let crc = crc32(&data[start..]);
+    data.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib(raw: &[u8]) -> Vec<u8> {
+    // deflate with a 32K window, no preset dictionary
+    let mut data = vec![0x78, 0x01];
+    let blocks = raw.chunks(STORED_BLOCK_SIZE).count();
+    for (i, block) in raw.chunks(STORED_BLOCK_SIZE).enumerate() {
+        let last = if i + 1 == blocks { 0x01 } else { 0x00 };
+        let length = block.len() as u16;
+        data.push(last);
+        data.extend_from_slice(&length.to_le_bytes());
+        data.extend_from_slice(&(!length).to_le_bytes());
+        data.extend_from_slice(block);
+    }
+    data.extend_from_slice(&adler32(raw).to_be_bytes());
+    data
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in bytes.iter() {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 0x01 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
@@ -0,0 +1,251 @@
+use std::io::Write;
+
+pub const SERIAL:   (u16, u16) = (0xff01, 0xff02);
+
+pub const SB:       u16 = 0xff01;
+pub const SC:       u16 = 0xff02;
+
+// The internal clock shifts at 8192 Hz, 512 T-cycles per bit
+const TRANSFER_CYCLES: u32 = 512 * 8;
+
+// Whatever sits at the other end of the link cable.
+pub trait SerialEndpoint {
+    // We drive the clock: shifts `byte` out and returns the byte shifted in.
+    fn transfer(&mut self, byte: u8) -> u8;
+
+    // Our SB while we wait for the other end to drive the clock, None while
+    // no externally clocked transfer is pending. Clocking a side that isn't
+    // waiting shifts in 0xff and leaves its SB alone.
+    fn publish(&mut self, _byte: Option<u8>) {}
+
+    // A byte the other end clocked in while we were waiting.
+    fn receive(&mut self) -> Option<u8> {
+        None
+    }
+
+    // The cycles the emulator ran, for endpoints that keep clocks in sync.
+    fn tick(&mut self, _cycles: u32) {}
+}
+
+// Records every transmitted byte, the way test ROMs report their results.
+// Nothing is connected, so 0xff is shifted back in.
+pub struct CaptureSink {
+    output: Box<dyn Write>,
+}
+
+impl CaptureSink {
+    pub fn new(output: Box<dyn Write>) -> CaptureSink {
+        CaptureSink { output }
+    }
+}
+
+impl SerialEndpoint for CaptureSink {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        if let Err(e) = self.output.write_all(&[byte]).and_then(|_| self.output.flush()) {
+            eprintln!("Can't capture serial output: {}", e);
+        }
+        0xff
+    }
+}
+
+pub struct Serial {
+    sb: u8,
+    // only bit 7 (transfer running) and bit 0 (internal clock) are stored
+    sc: u8,
+    cycles_left: u32,
+    endpoint: Option<Box<dyn SerialEndpoint>>,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0,
+            sc: 0,
+            cycles_left: 0,
+            endpoint: None,
+        }
+    }
+
+    pub fn connect(&mut self, endpoint: Box<dyn SerialEndpoint>) {
+        self.endpoint = Some(endpoint);
+        self.publish();
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            SB => self.sb,
+            SC => 0x7e | self.sc,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, byte: u8) {
+        match address {
+            SB => self.sb = byte,
+            SC => {
+                self.sc = byte & 0x81;
+                if self.sc == 0x81 {
+                    self.cycles_left = TRANSFER_CYCLES;
+                }
+            },
+            _ => return,
+        }
+        self.publish();
+    }
+
+    // Tells the other end whether it can clock a transfer with us.
+    fn publish(&mut self) {
+        let waiting = if self.sc == 0x80 { Some(self.sb) } else { None };
+        if let Some(ref mut endpoint) = self.endpoint {
+            endpoint.publish(waiting);
+        }
+    }
+
+    // Returns true when a transfer completed, which requests the serial
+    // interrupt.
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        if let Some(ref mut endpoint) = self.endpoint {
+            endpoint.tick(cycles);
+        }
+        if self.sc == 0x81 {
+            self.cycles_left = self.cycles_left.saturating_sub(cycles);
+            if self.cycles_left > 0 {
+                return false;
+            }
+            // without a cable the input line floats high
+            let incoming = match self.endpoint {
+                Some(ref mut endpoint) => endpoint.transfer(self.sb),
+                None => 0xff,
+            };
+            self.complete(incoming);
+            return true;
+        }
+
+        let incoming = match self.endpoint {
+            Some(ref mut endpoint) => endpoint.receive(),
+            None => None,
+        };
+        match incoming {
+            // the other end drove the clock, our SB went out as theirs came in
+            Some(byte) if self.sc == 0x80 => {
+                self.complete(byte);
+                true
+            },
+            // we stopped waiting before the transfer got to us
+            _ => false,
+        }
+    }
+
+    fn complete(&mut self, incoming: u8) {
+        self.sb = incoming;
+        self.sc &= 0x7f;
+        self.publish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use cpu::CPU;
+    use cpu::tests::cpu;
+    use interrupts::{Interrupt, INTERRUPT_FLAG};
+    use super::*;
+
+    struct LinkSide {
+        waiting: Option<u8>,
+        inbox: Option<u8>,
+    }
+
+    // One end of a cable between two CPUs in the same test
+    struct LinkPort {
+        sides: Rc<RefCell<[LinkSide; 2]>>,
+        side: usize,
+    }
+
+    fn link() -> (LinkPort, LinkPort) {
+        let sides = Rc::new(RefCell::new([
+            LinkSide { waiting: None, inbox: None },
+            LinkSide { waiting: None, inbox: None },
+        ]));
+        (LinkPort { sides: sides.clone(), side: 0 }, LinkPort { sides, side: 1 })
+    }
+
+    impl SerialEndpoint for LinkPort {
+        fn transfer(&mut self, byte: u8) -> u8 {
+            let mut sides = self.sides.borrow_mut();
+            let peer = &mut sides[1 - self.side];
+            match peer.waiting.take() {
+                Some(incoming) => {
+                    peer.inbox = Some(byte);
+                    incoming
+                },
+                None => 0xff,
+            }
+        }
+
+        fn publish(&mut self, byte: Option<u8>) {
+            self.sides.borrow_mut()[self.side].waiting = byte;
+        }
+
+        fn receive(&mut self) -> Option<u8> {
+            self.sides.borrow_mut()[self.side].inbox.take()
+        }
+    }
+
+    // LD A,sb; LDH (SB),A; then optionally LD A,sc; LDH (SC),A; and spin
+    fn program(sb: u8, sc: Option<u8>) -> Vec<u8> {
+        let mut program = vec![0x3e, sb, 0xe0, 0x01];
+        if let Some(sc) = sc {
+            program.extend_from_slice(&[0x3e, sc, 0xe0, 0x02]);
+        }
+        program.extend_from_slice(&[0x18, 0xfe]);
+        program
+    }
+
+    fn linked(master: &[u8], slave: &[u8]) -> (CPU, CPU) {
+        let (master_port, slave_port) = link();
+        let mut master = cpu(master);
+        let mut slave = cpu(slave);
+        master.connect_serial(Box::new(master_port));
+        slave.connect_serial(Box::new(slave_port));
+        // long enough for one transfer
+        for _ in 0..2000 {
+            master.step();
+            slave.step();
+        }
+        (master, slave)
+    }
+
+    fn serial_requested(cpu: &CPU) -> bool {
+        cpu.peek(INTERRUPT_FLAG) & Interrupt::Serial.mask() != 0
+    }
+
+    #[test]
+    fn master_and_slave_swap_bytes() {
+        let (master, slave) = linked(&program(0x42, Some(0x81)), &program(0x99, Some(0x80)));
+        assert_eq!(master.peek(SB), 0x99);
+        assert_eq!(slave.peek(SB), 0x42);
+        // both transfers finished
+        assert_eq!(master.peek(SC), 0x7f);
+        assert_eq!(slave.peek(SC), 0x7e);
+        assert!(serial_requested(&master));
+        assert!(serial_requested(&slave));
+    }
+
+    #[test]
+    fn slave_that_isnt_waiting_is_left_alone() {
+        let (master, slave) = linked(&program(0x42, Some(0x81)), &program(0x99, None));
+        assert_eq!(master.peek(SB), 0xff);
+        assert!(serial_requested(&master));
+        assert_eq!(slave.peek(SB), 0x99);
+        assert!(!serial_requested(&slave));
+
+        // it only takes part once it starts its own transfer
+        let (master, slave) = linked(&program(0x42, None), &program(0x99, Some(0x80)));
+        assert_eq!(master.peek(SB), 0x42);
+        assert_eq!(slave.peek(SB), 0x99);
+        assert_eq!(slave.peek(SC), 0xfe);
+        assert!(!serial_requested(&slave));
+    }
+}
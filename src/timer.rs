@@ -0,0 +1,175 @@
+pub const TIMER:    (u16, u16) = (0xff04, 0xff07);
+
+pub const DIV:      u16 = 0xff04;
+pub const TIMA:     u16 = 0xff05;
+pub const TMA:      u16 = 0xff06;
+pub const TAC:      u16 = 0xff07;
+
+// Bit of the internal divider whose falling edge clocks TIMA, by TAC
+// frequency setting (4096, 262144, 65536 and 16384 Hz).
+static TAC_BITS: [u8; 4] = [9, 3, 5, 7];
+
+pub struct Timer {
+    // DIV is the upper byte of this counter, which advances every T-cycle
+    divider: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    // TIMA overflowed and reads 0x00 until TMA is loaded one M-cycle later
+    overflow: bool,
+    // the M-cycle in which TMA was just loaded, TIMA writes are ignored
+    reloaded: bool,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            divider: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflow: false,
+            reloaded: false,
+        }
+    }
+
+    pub fn skip_boot(&mut self) {
+        self.divider = 0xabcc;
+    }
+
+    // The signal that clocks TIMA: the selected divider bit ANDed with the
+    // timer enable bit. TIMA increments whenever it falls.
+    fn signal(&self) -> bool {
+        let bit = TAC_BITS[(self.tac & 0x03) as usize];
+        self.tac & 0x04 != 0 && (self.divider >> bit) & 0x01 != 0
+    }
+
+    fn increment(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            self.overflow = true;
+        }
+    }
+
+    // Advances the timer and returns whether it requested an interrupt.
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        let mut interrupt = false;
+        for _ in 0..cycles / 4 {
+            self.reloaded = false;
+            if self.overflow {
+                self.overflow = false;
+                self.reloaded = true;
+                self.tima = self.tma;
+                interrupt = true;
+            }
+            let signal = self.signal();
+            self.divider = self.divider.wrapping_add(4);
+            if signal && !self.signal() {
+                self.increment();
+            }
+        }
+        interrupt
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            DIV => (self.divider >> 8) as u8,
+            TIMA => self.tima,
+            TMA => self.tma,
+            TAC => self.tac | 0xf8,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, byte: u8) {
+        // Resetting DIV or changing TAC can pull the clock signal low, which
+        // increments TIMA just like a regular tick would.
+        let signal = self.signal();
+        match address {
+            DIV => self.divider = 0,
+            // writing TIMA during the overflow cycle cancels the reload, in
+            // the reload cycle itself the write is lost
+            TIMA if !self.reloaded => {
+                self.tima = byte;
+                self.overflow = false;
+            },
+            TMA => {
+                self.tma = byte;
+                if self.reloaded {
+                    self.tima = byte;
+                }
+            },
+            TAC => self.tac = byte & 0x07,
+            _ => {},
+        }
+        if signal && !self.signal() {
+            self.increment();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A timer counting at 262144 Hz, TIMA ticks whenever divider bit 3 falls
+    fn timer() -> Timer {
+        let mut timer = Timer::new();
+        timer.write(TAC, 0x05);
+        timer
+    }
+
+    #[test]
+    fn resetting_div_can_increment_tima() {
+        let mut timer = timer();
+        timer.tick(8);
+        assert_eq!(timer.read(TIMA), 0x00);
+        timer.write(DIV, 0x12);
+        assert_eq!(timer.read(DIV), 0x00);
+        assert_eq!(timer.read(TIMA), 0x01);
+        // with the bit already low nothing happens
+        timer.tick(4);
+        timer.write(DIV, 0x00);
+        assert_eq!(timer.read(TIMA), 0x01);
+    }
+
+    #[test]
+    fn changing_tac_can_increment_tima() {
+        let mut timer = timer();
+        timer.tick(8);
+        // bit 9 is still low
+        timer.write(TAC, 0x04);
+        assert_eq!(timer.read(TIMA), 0x01);
+        timer.write(TAC, 0x05);
+        assert_eq!(timer.read(TIMA), 0x01);
+        // so is the enable bit once it's cleared
+        timer.write(TAC, 0x01);
+        assert_eq!(timer.read(TIMA), 0x02);
+    }
+
+    #[test]
+    fn overflow_reloads_tma_one_cycle_later() {
+        let mut timer = timer();
+        timer.write(TMA, 0x80);
+        timer.write(TIMA, 0xff);
+        assert!(!timer.tick(16));
+        assert_eq!(timer.read(TIMA), 0x00);
+        assert!(timer.tick(4));
+        assert_eq!(timer.read(TIMA), 0x80);
+        // the write in the reload cycle is lost
+        timer.write(TIMA, 0x42);
+        assert_eq!(timer.read(TIMA), 0x80);
+    }
+
+    #[test]
+    fn writing_tima_cancels_the_reload() {
+        let mut timer = timer();
+        timer.write(TMA, 0x80);
+        timer.write(TIMA, 0xff);
+        timer.tick(16);
+        timer.write(TIMA, 0x42);
+        assert!(!timer.tick(4));
+        assert_eq!(timer.read(TIMA), 0x42);
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Access {
+    Read = 0x00,
+    Write,
+    Execute,
+}
+
+impl Access {
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+// Watches an inclusive address range for any of the accesses in `accesses`,
+// optionally only when the byte read, written or executed equals `value`.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub accesses: u8,
+    pub value: Option<u8>,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, address: u16, access: Access, byte: u8) -> bool {
+        self.accesses & access.mask() != 0
+            && address >= self.start && address <= self.end
+            && self.value.is_none_or(|value| value == byte)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kinds: String = [(Access::Read, 'r'), (Access::Write, 'w'), (Access::Execute, 'x')].iter()
+            .filter(|&&(access, _)| self.accesses & access.mask() != 0)
+            .map(|&(_, kind)| kind)
+            .collect();
+        write!(f, "{} ${:04x}", kinds, self.start)?;
+        if self.end != self.start {
+            write!(f, "-${:04x}", self.end)?;
+        }
+        if let Some(value) = self.value {
+            write!(f, " == ${:02x}", value)?;
+        }
+        Ok(())
+    }
+}
+
+// The access that tripped a watchpoint, and the watchpoint it tripped.
+#[derive(Clone, Copy)]
+pub struct WatchHit {
+    pub address: u16,
+    pub access: Access,
+    pub value: u8,
+    pub watchpoint: Watchpoint,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Read => write!(f, "Read ${:02x} from ${:04x}", self.value, self.address),
+            Access::Write => write!(f, "Wrote ${:02x} to ${:04x}", self.value, self.address),
+            Access::Execute => write!(f, "Executed ${:02x} at ${:04x}", self.value, self.address),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const CHANNELS:         u16 = 2;
+const BITS_PER_SAMPLE:  u16 = 16;
+const HEADER_SIZE:      u32 = 44;
+
+// A 16-bit stereo PCM WAV file, written as samples come in. The sizes in the
+// header are kept up to date so a run that gets killed still leaves a
+// playable file.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    data_size: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<WavWriter> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+
+        file.write_all(b"RIFF")?;
+        file.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        // format 1, integer PCM
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&CHANNELS.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter { file, data_size: 0 })
+    }
+
+    // Appends interleaved left/right samples.
+    pub fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        if samples.is_empty() {
+            return Ok(());
+        }
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for sample in samples.iter() {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.file.write_all(&bytes)?;
+        self.data_size += bytes.len() as u32;
+
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.file.write_all(&self.data_size.to_le_bytes())?;
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}